@@ -0,0 +1,270 @@
+use indicatif::{
+    MultiProgress,
+    ProgressDrawTarget,
+};
+use log::{
+    error,
+    info,
+};
+use rayon::prelude::*;
+use serde::Deserialize;
+use std::{
+    collections::{
+        HashMap,
+        HashSet,
+    },
+    error::Error,
+    fs,
+    path::{
+        Path,
+        PathBuf,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use crate::{
+    config::Config,
+    input::image_sequence::SequencePattern,
+};
+
+/// The extensions of the files picked up from a batch or watched directory.
+const VIDEO_EXTENSIONS: [&str; 8] = ["mp4", "mov", "mkv", "avi", "webm", "m4v", "mts", "mxf"];
+
+/// Configuration for the batch mode, which renders every video in a directory or matching a glob
+/// pattern with the same settings.
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BatchSettings {
+    /// A directory, whose videos are all rendered, or a glob pattern such as `shoot/**/*.mov`.
+    /// None renders `input.path` only.
+    pub input: Option<String>,
+    /// The directory the outputs are written to, created if it doesn't exist.
+    pub output_dir: PathBuf,
+    /// The file name of each output. `{stem}` is replaced by the file name of the input without
+    /// its extension, and `{ext}` by its extension.
+    pub name_template: String,
+    /// If `true`, inputs whose output already exists are skipped.
+    pub skip_existing: bool,
+    /// The number of clips rendered at once, one per core if none. Each clip's decoder and encoder
+    /// start their own threads on top of this.
+    pub parallel_clips: Option<usize>,
+}
+
+impl Default for BatchSettings {
+    fn default() -> Self {
+        BatchSettings {
+            input: None,
+            output_dir: PathBuf::from("output"),
+            name_template: String::from("{stem}_sticky.mp4"),
+            skip_existing: true,
+            parallel_clips: None,
+        }
+    }
+}
+
+impl BatchSettings {
+    /// Returns `true` if a batch of inputs is set.
+    pub fn is_enabled(&self) -> bool {
+        self.input.is_some()
+    }
+
+    /// Returns the output path of `input`.
+    fn output_path(&self, input: &Path) -> PathBuf {
+        output_path(&self.output_dir, &self.name_template, input)
+    }
+
+    /// Finds the inputs of the batch, in alphabetical order. Files that are the output of another
+    /// input are left out, so an output directory inside the input directory can be rendered
+    /// again. Fails if two inputs would be written to the same output, as they would overwrite
+    /// each other's output and checkpoints.
+    fn find_inputs(&self) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+        let pattern = self.input.as_deref().ok_or("batch: no input is set")?;
+        let mut inputs = if Path::new(pattern).is_dir() {
+            find_videos(Path::new(pattern))?
+        } else {
+            let mut inputs = Vec::new();
+            for path in glob::glob(pattern).map_err(|e| format!("batch: invalid pattern '{}': {}", pattern, e))? {
+                let path = path?;
+                if path.is_file() {
+                    inputs.push(path);
+                }
+            }
+            inputs.sort();
+            inputs
+        };
+
+        let outputs: HashSet<PathBuf> = inputs.iter().map(|input| self.output_path(input)).collect();
+        inputs.retain(|input| !outputs.contains(input));
+
+        let mut inputs_by_output: HashMap<PathBuf, &Path> = HashMap::new();
+        for input in &inputs {
+            if let Some(other) = inputs_by_output.insert(self.output_path(input), input) {
+                return Err(format!(
+                    "batch: '{}' and '{}' would both be written to '{}', rename one of them or add `{{ext}}` to the name template",
+                    other.display(),
+                    input.display(),
+                    self.output_path(input).display()
+                )
+                .into());
+            }
+        }
+        Ok(inputs)
+    }
+}
+
+/// The outcome of rendering one clip of a batch.
+enum ClipStatus {
+    Done,
+    /// The output already existed.
+    Skipped,
+    Failed(String),
+}
+
+/// A clip of a batch and how rendering it went.
+struct ClipResult {
+    input: PathBuf,
+    status: ClipStatus,
+    duration: Duration,
+}
+
+/// Renders every input of `config.batch` with `render`, several clips at once. Each clip gets a
+/// copy of `config` with its own input, output and still paths. A failed clip doesn't stop the
+/// others, but fails the batch once they are done.
+pub fn run(
+    config: &Config,
+    render: impl Fn(&Config, &MultiProgress) -> Result<(), Box<dyn Error>> + Sync,
+) -> Result<(), Box<dyn Error>> {
+    let batch = &config.batch;
+    // Clips are rendered on the rayon pool, so its size is the number of clips rendered at once.
+    // FFmpeg decodes and encodes each of them on its own threads
+    if let Some(parallel_clips) = batch.parallel_clips {
+        rayon::ThreadPoolBuilder::new().num_threads(parallel_clips).build_global()?;
+    }
+
+    let inputs = batch.find_inputs()?;
+    if inputs.is_empty() {
+        return Err(format!("batch: no videos found in '{}'", batch.input.as_deref().unwrap_or_default()).into());
+    }
+    fs::create_dir_all(&batch.output_dir)?;
+    info!(
+        "Rendering {} clips to {}, {} at once",
+        inputs.len(),
+        batch.output_dir.display(),
+        rayon::current_num_threads()
+    );
+
+    let progress = MultiProgress::with_draw_target(ProgressDrawTarget::stderr());
+    let results: Vec<ClipResult> = inputs
+        .into_par_iter()
+        .map(|input| {
+            let start = Instant::now();
+            let clip_config = clip_config(config, &input, &batch.output_path(&input));
+            let status = if batch.skip_existing && output_exists(&clip_config) {
+                ClipStatus::Skipped
+            } else {
+                match render(&clip_config, &progress) {
+                    Ok(()) => ClipStatus::Done,
+                    Err(e) => {
+                        error!("{}: {}", input.display(), e);
+                        ClipStatus::Failed(e.to_string())
+                    }
+                }
+            };
+            ClipResult { input, status, duration: start.elapsed() }
+        })
+        .collect();
+
+    print_summary(&results);
+    let failed = results.iter().filter(|result| matches!(result.status, ClipStatus::Failed(_))).count();
+    if failed > 0 {
+        return Err(format!("{} of {} clips failed", failed, results.len()).into());
+    }
+    Ok(())
+}
+
+/// Returns the videos directly in `dir`, in alphabetical order. Hidden files, such as temporary
+/// files, are left out.
+pub fn find_videos(dir: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut videos = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let path = entry.path();
+        let is_video = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| VIDEO_EXTENSIONS.contains(&extension.to_lowercase().as_str()));
+        if path.is_file() && is_video {
+            videos.push(path);
+        }
+    }
+    videos.sort();
+    Ok(videos)
+}
+
+/// Returns the path in `output_dir` named by `name_template` after `input`.
+pub fn output_path(output_dir: &Path, name_template: &str, input: &Path) -> PathBuf {
+    let stem = input.file_stem().map(|stem| stem.to_string_lossy()).unwrap_or_default();
+    let extension = input.extension().map(|extension| extension.to_string_lossy()).unwrap_or_default();
+    let file_name = name_template.replace("{stem}", &stem).replace("{ext}", &extension);
+    output_dir.join(file_name)
+}
+
+/// Returns a copy of `config` reading the clip `input` and writing it to `output_path`.
+pub fn clip_config(config: &Config, input: &Path, output_path: &Path) -> Config {
+    let mut clip_config = config.clone();
+    // Stills are named after the output, so the clips don't overwrite each other's
+    let output_stem = output_path.file_stem().map(|stem| stem.to_string_lossy()).unwrap_or_default();
+    let still_name = config.stills.path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+    clip_config.stills.path = output_path.with_file_name(format!("{}_{}", output_stem, still_name));
+    clip_config.input.path = input.to_path_buf();
+    clip_config.output.path = output_path.to_path_buf();
+    clip_config
+}
+
+/// Returns `true` if the clip was already rendered: the output exists, or the still at the end
+/// for renders that only save stills.
+fn output_exists(config: &Config) -> bool {
+    if config.stills.only {
+        return config.stills.path.exists();
+    }
+    match SequencePattern::parse(&config.output.path) {
+        Some(pattern) => pattern.path(config.output.start_number).exists(),
+        None => config.output.path.exists(),
+    }
+}
+
+/// Prints a table of the clips and how rendering them went.
+fn print_summary(results: &[ClipResult]) {
+    let names: Vec<String> = results.iter().map(|result| result.input.display().to_string()).collect();
+    let width = names.iter().map(|name| name.len()).max().unwrap_or(0).max("Input".len());
+
+    println!();
+    println!("{:<width$}  {:<7}  {:>8}  Error", "Input", "Result", "Time", width = width);
+    for (name, result) in names.iter().zip(results) {
+        let (label, message) = match &result.status {
+            ClipStatus::Done => ("done", ""),
+            ClipStatus::Skipped => ("skipped", ""),
+            ClipStatus::Failed(message) => ("failed", message.as_str()),
+        };
+        let time = match result.status {
+            ClipStatus::Skipped => String::from("-"),
+            _ => format!("{:.1}s", result.duration.as_secs_f64()),
+        };
+        let row = format!("{:<width$}  {:<7}  {:>8}  {}", name, label, time, message, width = width);
+        println!("{}", row.trim_end());
+    }
+
+    let count = |label: fn(&ClipStatus) -> bool| results.iter().filter(|result| label(&result.status)).count();
+    println!(
+        "\n{} done, {} skipped, {} failed",
+        count(|status| matches!(status, ClipStatus::Done)),
+        count(|status| matches!(status, ClipStatus::Skipped)),
+        count(|status| matches!(status, ClipStatus::Failed(_))),
+    );
+}
@@ -0,0 +1,424 @@
+use image::{
+    Rgba32FImage,
+    RgbaImage,
+};
+use log::info;
+use serde::Deserialize;
+use std::{
+    error::Error,
+    fs::{
+        self,
+        File,
+    },
+    io::{
+        self,
+        BufReader,
+        BufWriter,
+        Read,
+        Write,
+    },
+    path::{
+        Path,
+        PathBuf,
+    },
+    process::Command,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use crate::{
+    output::{
+        FrameSink,
+        OverwritePolicy,
+        temp_file::TempFile,
+    },
+    video_processors::{
+        EffectState,
+        trail_age::TrailAges,
+    },
+};
+
+/// Identifies checkpoint files and the version of their layout.
+const MAGIC: &[u8; 8] = b"STKCKPT3";
+
+/// The file holding the state, in the checkpoint directory.
+const STATE_FILE: &str = "state.bin";
+
+/// Configuration for checkpoints, which save the effect state of long renders so that they can
+/// be resumed after the process stops.
+#[derive(Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CheckpointSettings {
+    /// The time between two checkpoints in seconds of rendering, none to disable checkpoints.
+    pub interval_secs: Option<f64>,
+}
+
+/// Returns the directory holding the checkpoint and the encoded parts of the render to `output`.
+pub fn checkpoint_dir(output: &Path) -> PathBuf {
+    let name = output.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+    output.with_file_name(format!(".{}.checkpoint", name))
+}
+
+/// Returns the path of the encoded part numbered `index` in the checkpoint directory `dir`.
+pub fn part_path(dir: &Path, index: usize) -> PathBuf {
+    dir.join(format!("part_{:04}.mp4", index))
+}
+
+/// The state of a render after a frame, everything needed to continue with the next one.
+pub struct RenderState {
+    /// The number of frames processed.
+    pub frame_count: usize,
+    /// The timestamp of the last processed frame, in milliseconds.
+    pub timestamp_ms: f64,
+    /// The index of the time segment the last frame was in.
+    pub active_segment: Option<usize>,
+    /// The last processed source frame, which motion is detected against.
+    pub previous_frame: Option<RgbaImage>,
+    /// The state of every stage of the effect stack, none if no stack was running.
+    pub stack_states: Option<Vec<Option<EffectState>>>,
+}
+
+/// Saves checkpoints of a render to a video, which is encoded in parts so the frames before the
+/// last checkpoint are kept.
+pub struct Checkpointer {
+    dir: PathBuf,
+    interval: Duration,
+    last_save: Instant,
+    /// The input and its length in bytes, to make sure a checkpoint is resumed with the same one.
+    input: PathBuf,
+    input_len: u64,
+    /// The size of the processed frames.
+    size: (u32, u32),
+    /// The number of complete parts.
+    parts: usize,
+    /// The number of frames processed at the last checkpoint.
+    saved_frame_count: usize,
+}
+
+impl Checkpointer {
+    /// Starts checkpointing a new render of `input` to `output` every `interval_secs`, removing
+    /// any checkpoint of an earlier render.
+    pub fn start(output: &Path, input: &Path, size: (u32, u32), interval_secs: f64) -> Result<Self, Box<dyn Error>> {
+        let dir = checkpoint_dir(output);
+        if dir.exists() {
+            info!("Removing the checkpoint of an earlier render of {}, use --resume to continue it", output.display());
+            fs::remove_dir_all(&dir)?;
+        }
+        fs::create_dir_all(&dir)?;
+        Ok(Checkpointer {
+            dir,
+            interval: Duration::from_secs_f64(interval_secs),
+            last_save: Instant::now(),
+            input: input.to_path_buf(),
+            input_len: input_len(input),
+            size,
+            parts: 0,
+            saved_frame_count: 0,
+        })
+    }
+
+    /// Continues checkpointing from the last checkpoint of the render of `input` to `output`,
+    /// returning the state to continue from.
+    pub fn resume(
+        output: &Path,
+        input: &Path,
+        size: (u32, u32),
+        interval_secs: f64,
+    ) -> Result<(Self, RenderState), Box<dyn Error>> {
+        let dir = checkpoint_dir(output);
+        let path = dir.join(STATE_FILE);
+        if !path.exists() {
+            return Err(format!("There is no checkpoint to resume for {}", output.display()).into());
+        }
+        let mut reader = BufReader::new(File::open(&path)?);
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(format!("{} isn't a checkpoint of this version", path.display()).into());
+        }
+
+        // The saved input can only match if its name is as long as this one
+        let input_name = input.to_string_lossy();
+        let saved_input = read_bytes(&mut reader, input_name.len())
+            .map_err(|e| format!("The checkpoint is damaged or was saved for another input: {}", e))?;
+        let saved_input = PathBuf::from(String::from_utf8_lossy(&saved_input).into_owned());
+        let saved_input_len = read_u64(&mut reader)?;
+        if saved_input != input || saved_input_len != input_len(input) {
+            return Err(format!("The checkpoint was saved for another input, {}", saved_input.display()).into());
+        }
+        let saved_size = (read_u32(&mut reader)?, read_u32(&mut reader)?);
+        if saved_size != size {
+            return Err(format!(
+                "The checkpoint was saved for {}x{} frames, not {}x{}",
+                saved_size.0, saved_size.1, size.0, size.1
+            )
+            .into());
+        }
+        let parts = read_u64(&mut reader)? as usize;
+        let state = read_state(&mut reader, size)?;
+
+        // A part started after the checkpoint is incomplete, and rendered again
+        let mut index = parts;
+        while part_path(&dir, index).exists() {
+            fs::remove_file(part_path(&dir, index))?;
+            index += 1;
+        }
+
+        let checkpointer = Checkpointer {
+            dir,
+            interval: Duration::from_secs_f64(interval_secs),
+            last_save: Instant::now(),
+            input: input.to_path_buf(),
+            input_len: saved_input_len,
+            size,
+            parts,
+            saved_frame_count: state.frame_count,
+        };
+        Ok((checkpointer, state))
+    }
+
+    /// Returns `true` if a checkpoint of the render to `output` exists.
+    pub fn exists(output: &Path) -> bool {
+        checkpoint_dir(output).join(STATE_FILE).exists()
+    }
+
+    /// Returns the checkpoint directory, which holds the encoded parts.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Returns the number of complete parts.
+    pub fn parts(&self) -> usize {
+        self.parts
+    }
+
+    /// Returns `true` if the interval passed since the last checkpoint, and frames were processed
+    /// since then.
+    pub fn is_due(&self, frame_count: usize) -> bool {
+        self.last_save.elapsed() >= self.interval && self.has_new_frames(frame_count)
+    }
+
+    /// Returns `true` if frames were processed since the last checkpoint.
+    pub fn has_new_frames(&self, frame_count: usize) -> bool {
+        frame_count > self.saved_frame_count
+    }
+
+    /// Completes the current part of the video in `sink`, then saves `state`. The state is
+    /// written to a temporary file first, so a crash while saving keeps the previous checkpoint.
+    pub fn save(&mut self, sink: &mut dyn FrameSink, state: &RenderState) -> Result<(), Box<dyn Error>> {
+        sink.split()?;
+        let parts = self.parts + 1;
+
+        let path = self.dir.join(STATE_FILE);
+        let temp_file = TempFile::next_to(&path, "bin");
+        let mut writer = BufWriter::new(File::create(temp_file.path())?);
+        writer.write_all(MAGIC)?;
+        write_bytes(&mut writer, self.input.to_string_lossy().as_bytes())?;
+        write_u64(&mut writer, self.input_len)?;
+        write_u32(&mut writer, self.size.0)?;
+        write_u32(&mut writer, self.size.1)?;
+        write_u64(&mut writer, parts as u64)?;
+        write_state(&mut writer, state)?;
+        writer.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        temp_file.persist(OverwritePolicy::Overwrite)?;
+
+        self.parts = parts;
+        self.saved_frame_count = state.frame_count;
+        self.last_save = Instant::now();
+        info!("Saved a checkpoint at frame {} ({:.1} s)", state.frame_count, state.timestamp_ms / 1000.0);
+        Ok(())
+    }
+
+    /// Joins the encoded parts into the video at `path`, once the last part is complete.
+    pub fn concat(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let mut parts = Vec::new();
+        while part_path(&self.dir, parts.len()).exists() {
+            parts.push(part_path(&self.dir, parts.len()));
+        }
+        match parts.as_slice() {
+            [] => return Err("The render has no encoded parts".into()),
+            // A render without checkpoints in between needs no joining
+            [part] => fs::rename(part, path)?,
+            _ => {
+                // The concat demuxer reads the parts one after another, shifting the timestamps of
+                // each to follow the one before
+                let list_path = self.dir.join("parts.txt");
+                let list: String = parts
+                    .iter()
+                    .filter_map(|part| part.file_name())
+                    .map(|name| format!("file '{}'\n", name.to_string_lossy()))
+                    .collect();
+                fs::write(&list_path, list)?;
+                let status = Command::new("ffmpeg")
+                    .args(["-n", "-loglevel", "error", "-f", "concat", "-safe", "0", "-i"])
+                    .arg(&list_path)
+                    .args(["-c", "copy"])
+                    .arg(path)
+                    .status()
+                    .map_err(|e| format!("Failed to start FFmpeg, which joins the parts of the video: {}", e))?;
+                if !status.success() {
+                    return Err(format!("FFmpeg failed to join the parts in {}", self.dir.display()).into());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes the checkpoint and the parts, once the output is complete.
+    pub fn remove(self) -> Result<(), Box<dyn Error>> {
+        fs::remove_dir_all(&self.dir)?;
+        Ok(())
+    }
+}
+
+/// Returns the length of the file at `path` in bytes, `0` if it isn't a file, such as an image
+/// sequence pattern.
+fn input_len(path: &Path) -> u64 {
+    fs::metadata(path).map_or(0, |metadata| metadata.len())
+}
+
+/// Writes `state` with frames of the checkpoint size.
+fn write_state(writer: &mut impl Write, state: &RenderState) -> io::Result<()> {
+    write_u64(writer, state.frame_count as u64)?;
+    write_f64(writer, state.timestamp_ms)?;
+    write_option(writer, state.active_segment, |writer, index| write_u64(writer, index as u64))?;
+    write_option(writer, state.previous_frame.as_ref(), |writer, frame| writer.write_all(frame.as_raw()))?;
+    write_option(writer, state.stack_states.as_ref(), |writer, states| {
+        write_u64(writer, states.len() as u64)?;
+        for state in states {
+            write_option(writer, state.as_ref(), write_effect_state)?;
+        }
+        Ok(())
+    })
+}
+
+/// Reads a state written by `write_state`.
+fn read_state(reader: &mut impl Read, size: (u32, u32)) -> Result<RenderState, Box<dyn Error>> {
+    let frame_count = read_u64(reader)? as usize;
+    let timestamp_ms = read_f64(reader)?;
+    let active_segment = read_option(reader, |reader| Ok(read_u64(reader)? as usize))?;
+    let previous_frame = read_option(reader, |reader| {
+        let mut bytes = vec![0; size.0 as usize * size.1 as usize * 4];
+        reader.read_exact(&mut bytes)?;
+        RgbaImage::from_raw(size.0, size.1, bytes).ok_or_else(|| "Invalid previous frame".into())
+    })?;
+    let stack_states = read_option(reader, |reader| {
+        let count = read_u64(reader)?;
+        (0..count).map(|_| read_option(reader, |reader| read_effect_state(reader, size))).collect()
+    })?;
+    Ok(RenderState { frame_count, timestamp_ms, active_segment, previous_frame, stack_states })
+}
+
+/// Writes the canvas, hue, trail ages and decay time of an effect.
+fn write_effect_state(writer: &mut impl Write, state: &EffectState) -> io::Result<()> {
+    for value in state.canvas.as_raw() {
+        writer.write_all(&value.to_le_bytes())?;
+    }
+    write_f64(writer, state.rainbow_hue)?;
+    write_option(writer, state.trail_ages.as_ref(), |writer, trail_ages| {
+        for stamp in trail_ages.stamps() {
+            writer.write_all(&stamp.to_le_bytes())?;
+        }
+        Ok(())
+    })?;
+    write_option(writer, state.last_decay_ms, write_f64)
+}
+
+/// Reads an effect state written by `write_effect_state` with a canvas of `size`.
+fn read_effect_state(reader: &mut impl Read, size: (u32, u32)) -> Result<EffectState, Box<dyn Error>> {
+    let pixel_count = size.0 as usize * size.1 as usize;
+    let canvas = Rgba32FImage::from_raw(size.0, size.1, read_f32s(reader, pixel_count * 4)?).ok_or("Invalid canvas")?;
+    let rainbow_hue = read_f64(reader)?;
+    let trail_ages = read_option(reader, |reader| Ok(TrailAges::from_stamps(read_f32s(reader, pixel_count)?)))?;
+    let last_decay_ms = read_option(reader, |reader| Ok(read_f64(reader)?))?;
+    Ok(EffectState { canvas, rainbow_hue, trail_ages, last_decay_ms })
+}
+
+/// Writes a flag for whether `value` is set, followed by the value if it is.
+fn write_option<W: Write, T>(
+    writer: &mut W,
+    value: Option<T>,
+    write: impl FnOnce(&mut W, T) -> io::Result<()>,
+) -> io::Result<()> {
+    match value {
+        Some(value) => {
+            writer.write_all(&[1])?;
+            write(writer, value)
+        }
+        None => writer.write_all(&[0]),
+    }
+}
+
+/// Reads a value written by `write_option`.
+fn read_option<R: Read, T>(
+    reader: &mut R,
+    read: impl FnOnce(&mut R) -> Result<T, Box<dyn Error>>,
+) -> Result<Option<T>, Box<dyn Error>> {
+    let mut flag = [0];
+    reader.read_exact(&mut flag)?;
+    match flag[0] {
+        0 => Ok(None),
+        1 => Ok(Some(read(reader)?)),
+        _ => Err("The checkpoint is damaged".into()),
+    }
+}
+
+/// Writes `bytes`, preceded by their length.
+fn write_bytes(writer: &mut impl Write, bytes: &[u8]) -> io::Result<()> {
+    write_u64(writer, bytes.len() as u64)?;
+    writer.write_all(bytes)
+}
+
+/// Reads bytes written by `write_bytes`, failing before allocating them if there are more than
+/// `max_len`, as a damaged length could ask for any amount of memory.
+fn read_bytes(reader: &mut impl Read, max_len: usize) -> io::Result<Vec<u8>> {
+    let len = read_u64(reader)?;
+    if len > max_len as u64 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} bytes were saved where at most {} were expected", len, max_len),
+        ));
+    }
+    let mut bytes = vec![0; len as usize];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn write_u32(writer: &mut impl Write, value: u32) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn write_u64(writer: &mut impl Write, value: u64) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+/// Reads `count` values written one after another with `f32::to_le_bytes`.
+fn read_f32s(reader: &mut impl Read, count: usize) -> io::Result<Vec<f32>> {
+    let mut bytes = vec![0; count * 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes.chunks_exact(4).map(|chunk| f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]])).collect())
+}
+
+fn write_f64(writer: &mut impl Write, value: f64) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn read_f64(reader: &mut impl Read) -> io::Result<f64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(f64::from_le_bytes(bytes))
+}
@@ -0,0 +1,79 @@
+use std::error::Error;
+
+use crate::input::raw::RawPixelFormat;
+
+/// The usage printed for `--help` and for invalid arguments.
+pub const USAGE: &str = "Usage: sticky_frame [--resume] [--raw WIDTHxHEIGHT@FPS] [--pixel-format rgb24|rgba]
+
+Options:
+  --resume                  Continue an interrupted render from its last checkpoint
+  --raw WIDTHxHEIGHT@FPS    Read raw frames from stdin and write raw frames to stdout
+  --pixel-format FORMAT     The layout of the raw frames, rgb24 (default) or rgba
+  --help                    Print this help";
+
+/// The layout of a raw video stream on stdin and stdout.
+#[derive(Clone, Copy)]
+pub struct RawVideo {
+    pub width: u32,
+    pub height: u32,
+    pub frame_rate: f32,
+    pub pixel_format: RawPixelFormat,
+}
+
+/// Holds the command line arguments.
+#[derive(Default)]
+pub struct Args {
+    /// The raw video stream to process instead of the configured input and output.
+    pub raw: Option<RawVideo>,
+    /// If `true`, an interrupted render is continued from its last checkpoint.
+    pub resume: bool,
+    /// If `true`, only the usage is printed.
+    pub help: bool,
+}
+
+impl Args {
+    /// Parses the arguments the program was started with.
+    pub fn parse() -> Result<Self, Box<dyn Error>> {
+        Self::parse_from(std::env::args().skip(1))
+    }
+
+    /// Parses `args`, without the program name.
+    fn parse_from(args: impl IntoIterator<Item = String>) -> Result<Self, Box<dyn Error>> {
+        let mut parsed = Args::default();
+        let mut raw_size = None;
+        let mut pixel_format = RawPixelFormat::Rgb24;
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+            match arg.as_str() {
+                "--raw" => raw_size = Some(parse_raw_size(&value()?)?),
+                "--pixel-format" => {
+                    pixel_format = match value()?.as_str() {
+                        "rgb24" => RawPixelFormat::Rgb24,
+                        "rgba" => RawPixelFormat::Rgba,
+                        other => return Err(format!("Unknown pixel format '{}', expected rgb24 or rgba", other).into()),
+                    }
+                }
+                "--resume" => parsed.resume = true,
+                "--help" | "-h" => parsed.help = true,
+                _ => return Err(format!("Unknown argument '{}'", arg).into()),
+            }
+        }
+        parsed.raw = raw_size.map(|(width, height, frame_rate)| RawVideo { width, height, frame_rate, pixel_format });
+        Ok(parsed)
+    }
+}
+
+/// Parses a raw video size such as `1920x1080@30` into its width, height and frame rate.
+fn parse_raw_size(value: &str) -> Result<(u32, u32, f32), Box<dyn Error>> {
+    let invalid = || format!("Invalid raw video size '{}', expected WIDTHxHEIGHT@FPS such as 1920x1080@30", value);
+    let (size, frame_rate) = value.split_once('@').ok_or_else(invalid)?;
+    let (width, height) = size.split_once('x').ok_or_else(invalid)?;
+    let width: u32 = width.parse().map_err(|_| invalid())?;
+    let height: u32 = height.parse().map_err(|_| invalid())?;
+    let frame_rate: f32 = frame_rate.parse().map_err(|_| invalid())?;
+    if width == 0 || height == 0 || frame_rate <= 0.0 {
+        return Err(invalid().into());
+    }
+    Ok((width, height, frame_rate))
+}
@@ -0,0 +1,179 @@
+use serde::Deserialize;
+use std::{
+    error::Error,
+    fs,
+    path::Path,
+};
+
+use crate::{
+    batch::BatchSettings,
+    checkpoint::CheckpointSettings,
+    input::InputSettings,
+    live::LiveSettings,
+    output::{
+        OutputSettings,
+        stills::StillSettings,
+    },
+    preprocess::{
+        Crop,
+        PreprocessSettings,
+    },
+    preview::PreviewSettings,
+    video_processors::{
+        EffectSettings,
+        audio::{
+            AudioFeature,
+            AudioModulation,
+        },
+        keyframes::KeyframeTrack,
+        segments::Segment,
+        stack::EffectStage,
+    },
+    watch::WatchSettings,
+};
+
+/// The config file read from the working directory, if it exists.
+pub const CONFIG_PATH: &str = "sticky_frame.toml";
+
+/// Holds everything that can be set in the config file.
+#[derive(Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub effect: EffectSettings,
+    /// The video or image sequence the frames are read from.
+    pub input: InputSettings,
+    /// The rotation, crop and scale applied to the frames before the effect.
+    pub preprocess: PreprocessSettings,
+    /// The settings of the rendered video.
+    pub output: OutputSettings,
+    /// The still images saved from the rendered frames.
+    pub stills: StillSettings,
+    /// The settings of the preview mode.
+    pub preview: PreviewSettings,
+    /// The settings of the live mode.
+    pub live: LiveSettings,
+    /// The videos rendered in batch mode instead of `input.path`.
+    pub batch: BatchSettings,
+    /// The directory watched for videos instead of rendering `input.path`.
+    pub watch: WatchSettings,
+    /// The checkpoints saved during long renders.
+    pub checkpoint: CheckpointSettings,
+    /// Tracks animating numeric fields of the effect settings over time.
+    pub keyframes: Vec<KeyframeTrack>,
+    /// Numeric fields of the effect settings driven by the audio track.
+    pub audio: Vec<AudioModulation>,
+    /// The time ranges the effect is applied to, the whole video if empty.
+    pub segments: Vec<Segment>,
+    /// The effects applied one after another, only `effect.mode` if empty.
+    pub stack: Vec<EffectStage>,
+}
+
+impl Config {
+    /// Loads the config from `path`, falling back to the default settings if the file doesn't exist.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+        let contents = fs::read_to_string(path)?;
+        let mut config: Config =
+            toml::from_str(&contents).map_err(|e| format!("{}: {}", path.display(), e))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks the keyframe tracks, audio modulations and segments, and puts the tracks and
+    /// segments in chronological order.
+    fn validate(&mut self) -> Result<(), Box<dyn Error>> {
+        for track in &mut self.keyframes {
+            if self.effect.numeric_field_mut(&track.field).is_none() {
+                return Err(format!("keyframes: '{}' is not a numeric effect setting of this config", track.field).into());
+            }
+            if track.keyframes.is_empty() {
+                return Err(format!("keyframes: the track for '{}' has no keyframes", track.field).into());
+            }
+            track.keyframes.sort_by(|a, b| a.time_ms.total_cmp(&b.time_ms));
+        }
+
+        for modulation in &self.audio {
+            if self.effect.numeric_field_mut(&modulation.field).is_none() {
+                return Err(format!("audio: '{}' is not a numeric effect setting of this config", modulation.field).into());
+            }
+            if let AudioFeature::Band([low_hz, high_hz]) = modulation.feature
+                && high_hz <= low_hz
+            {
+                return Err(format!("audio: the band of '{}' ends below its start", modulation.field).into());
+            }
+        }
+
+        if self.effect.n_frames_step == 0 {
+            return Err("effect: n_frames_step must be at least 1".into());
+        }
+        if self.input.frame_rate <= 0.0 {
+            return Err("input: the frame rate must be positive".into());
+        }
+        if let Some(Crop::Rect { width, height, .. }) = self.preprocess.crop
+            && (width < 2 || height < 2)
+        {
+            return Err("preprocess: the crop must be at least 2x2 pixels".into());
+        }
+        if self.preprocess.width.is_some_and(|width| width < 2) || self.preprocess.height.is_some_and(|height| height < 2) {
+            return Err("preprocess: the scaled width and height must be at least 2 pixels".into());
+        }
+        if self.output.bit_depth != 8 && self.output.bit_depth != 16 {
+            return Err(format!("output: the bit depth must be 8 or 16, got {}", self.output.bit_depth).into());
+        }
+        if !(2..=256).contains(&self.output.animation.colors) {
+            return Err("output.animation: the number of colors must be between 2 and 256".into());
+        }
+        if self.output.animation.frame_rate.is_some_and(|frame_rate| frame_rate <= 0.0) {
+            return Err("output.animation: the frame rate must be positive".into());
+        }
+        if self.output.frame_rate.is_some_and(|frame_rate| frame_rate <= 0.0) {
+            return Err("output: the frame rate must be positive".into());
+        }
+        if self.stills.interval_ms.is_some_and(|interval_ms| interval_ms <= 0.0) {
+            return Err("stills: the interval must be positive".into());
+        }
+        if self.stills.only && !self.stills.is_enabled() {
+            return Err("stills: `only` is set, but no still is requested".into());
+        }
+        if self.live.enabled && self.preview.enabled {
+            return Err("live: the live mode can't be previewed, disable one of them".into());
+        }
+        if self.live.width == 0 || self.live.height == 0 || self.live.frame_rate <= 0.0 {
+            return Err("live: the width, height and frame rate must be positive".into());
+        }
+        if self.batch.is_enabled() && (self.preview.enabled || self.live.enabled) {
+            return Err("batch: the batch mode can't be combined with the preview or live mode".into());
+        }
+        if self.batch.is_enabled() && !self.batch.name_template.contains("{stem}") {
+            return Err("batch: the name template needs `{stem}`, or every clip would be written to the same file".into());
+        }
+        if self.batch.parallel_clips == Some(0) {
+            return Err("batch: the number of parallel clips must be positive".into());
+        }
+        if self.watch.is_enabled() && (self.preview.enabled || self.live.enabled || self.batch.is_enabled()) {
+            return Err("watch: the watch mode can't be combined with the preview, live or batch mode".into());
+        }
+        if self.watch.is_enabled() && !self.watch.name_template.contains("{stem}") {
+            return Err("watch: the name template needs `{stem}`, or every video would be written to the same file".into());
+        }
+        if self.watch.stable_secs < 0.0 || self.watch.poll_interval_secs <= 0.0 {
+            return Err("watch: the stable time can't be negative and the poll interval must be positive".into());
+        }
+        if self.checkpoint.interval_secs.is_some_and(|interval_secs| interval_secs <= 0.0) {
+            return Err("checkpoint: the interval must be positive".into());
+        }
+        if self.preview.scale <= 0.0 || self.preview.scale > 1.0 {
+            return Err("preview: the scale must be between 0.0 and 1.0".into());
+        }
+
+        for segment in &self.segments {
+            if segment.end_ms.is_some_and(|end| end <= segment.start_ms) {
+                return Err(format!("segments: the segment starting at {} ms ends before it starts", segment.start_ms).into());
+            }
+        }
+        self.segments.sort_by(|a, b| a.start_ms.total_cmp(&b.start_ms));
+        Ok(())
+    }
+}
@@ -0,0 +1,181 @@
+use image::{
+    DynamicImage,
+    ImageFormat,
+    Rgba32FImage,
+    RgbaImage,
+};
+use std::{
+    error::Error,
+    fs,
+    path::{
+        Path,
+        PathBuf,
+    },
+};
+use video_rs::Time;
+
+use crate::input::FrameSource;
+
+/// A file name with a printf-style frame number, such as `shot_%05d.png`.
+pub struct SequencePattern {
+    directory: PathBuf,
+    prefix: String,
+    /// The minimum number of digits, padded with zeros. Zero for `%d`.
+    digits: usize,
+    suffix: String,
+}
+
+impl SequencePattern {
+    /// Parses the file name of `path`, none if it has no `%d` or `%0Nd` frame number.
+    pub fn parse(path: &Path) -> Option<Self> {
+        let file_name = path.file_name()?.to_str()?;
+        let start = file_name.find('%')?;
+        let rest = &file_name[start + 1..];
+        let end = rest.find('d')?;
+        let width = &rest[..end];
+        if !width.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        Some(SequencePattern {
+            directory: path.parent().map(Path::to_path_buf).unwrap_or_default(),
+            prefix: file_name[..start].to_string(),
+            digits: width.parse().unwrap_or(0),
+            suffix: rest[end + 1..].to_string(),
+        })
+    }
+
+    /// Returns the path of the frame numbered `number`.
+    pub fn path(&self, number: u64) -> PathBuf {
+        self.directory.join(format!("{}{:0width$}{}", self.prefix, number, self.suffix, width = self.digits))
+    }
+
+    /// Returns the frame number of the file named `file_name`, none if it doesn't match.
+    fn number_of(&self, file_name: &str) -> Option<u64> {
+        let digits = file_name.strip_prefix(&self.prefix)?.strip_suffix(&self.suffix)?;
+        if digits.is_empty() || digits.len() < self.digits || !digits.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        digits.parse().ok()
+    }
+
+    /// Lists the frame numbers of the files matching the pattern, in order.
+    pub fn find_frames(&self) -> Result<Vec<u64>, Box<dyn Error>> {
+        let directory = if self.directory.as_os_str().is_empty() { Path::new(".") } else { &self.directory };
+        let mut numbers = Vec::new();
+        for entry in fs::read_dir(directory)? {
+            if let Some(number) = entry?.file_name().to_str().and_then(|name| self.number_of(name)) {
+                numbers.push(number);
+            }
+        }
+        numbers.sort_unstable();
+        Ok(numbers)
+    }
+
+    /// Returns the pattern as it was written.
+    pub fn display(&self) -> String {
+        let width = if self.digits > 0 { format!("0{}", self.digits) } else { String::new() };
+        self.directory.join(format!("{}%{}d{}", self.prefix, width, self.suffix)).display().to_string()
+    }
+}
+
+/// Reads the frames of a numbered image sequence. Frame `n` is shown `n / frame_rate` seconds
+/// after the first one, so gaps in the numbering keep their duration.
+pub struct ImageSequenceSource {
+    pattern: SequencePattern,
+    /// The frame numbers of the sequence, in order.
+    numbers: Vec<u64>,
+    frame_rate: f32,
+    size: (u32, u32),
+    /// The index in `numbers` of the next frame to read.
+    position: usize,
+}
+
+impl ImageSequenceSource {
+    /// Finds the frames matching `pattern`, shown at `frame_rate` frames per second.
+    pub fn open(pattern: SequencePattern, frame_rate: f32) -> Result<Self, Box<dyn Error>> {
+        let numbers = pattern.find_frames()?;
+        let first = *numbers.first().ok_or_else(|| format!("No frames found matching {}", pattern.display()))?;
+        let size = image::image_dimensions(pattern.path(first))?;
+        Ok(ImageSequenceSource { pattern, numbers, frame_rate, size, position: 0 })
+    }
+
+    /// Returns the timestamp of the frame numbered `number`, in milliseconds.
+    fn timestamp_ms(&self, number: u64) -> f64 {
+        (number - self.numbers[0]) as f64 * 1000.0 / self.frame_rate as f64
+    }
+}
+
+impl FrameSource for ImageSequenceSource {
+    fn size(&self) -> (u32, u32) {
+        self.size
+    }
+
+    fn frame_rate(&self) -> f32 {
+        self.frame_rate
+    }
+
+    fn frame_count(&self) -> Result<u64, Box<dyn Error>> {
+        Ok(self.numbers.len() as u64)
+    }
+
+    fn rotation(&self) -> Result<u32, Box<dyn Error>> {
+        Ok(0)
+    }
+
+    fn audio_path(&self) -> Option<&Path> {
+        None
+    }
+
+    fn seek(&mut self, timestamp_ms: f64) -> Result<(), Box<dyn Error>> {
+        // Lands on the last frame at or before `timestamp_ms`, allowing for rounding in the timestamps
+        let after = self.numbers.partition_point(|&number| self.timestamp_ms(number) <= timestamp_ms + 1e-3);
+        self.position = after.saturating_sub(1);
+        Ok(())
+    }
+
+    fn next_frame(&mut self) -> Result<Option<(RgbaImage, Time)>, Box<dyn Error>> {
+        let Some(&number) = self.numbers.get(self.position) else {
+            return Ok(None);
+        };
+        self.position += 1;
+
+        let path = self.pattern.path(number);
+        let image = image::open(&path)?;
+        let frame = match ImageFormat::from_path(&path) {
+            Ok(ImageFormat::OpenExr) => linear_to_srgb(image.to_rgba32f()),
+            _ => image.to_rgba8(),
+        };
+        if frame.dimensions() != self.size {
+            return Err(format!("{} doesn't have the size of the first frame", path.display()).into());
+        }
+        Ok(Some((frame, Time::from_secs_f64(self.timestamp_ms(number) / 1000.0))))
+    }
+}
+
+/// Encodes a linear-light image, as stored in EXR files, with the sRGB transfer curve.
+pub fn linear_to_srgb(image: Rgba32FImage) -> RgbaImage {
+    let (width, height) = image.dimensions();
+    RgbaImage::from_fn(width, height, |x, y| {
+        let [r, g, b, a] = image.get_pixel(x, y).0;
+        let encode = |value: f32| {
+            let value = value.clamp(0.0, 1.0);
+            let value = if value <= 0.003_130_8 { value * 12.92 } else { 1.055 * value.powf(1.0 / 2.4) - 0.055 };
+            (value * 255.0).round() as u8
+        };
+        image::Rgba([encode(r), encode(g), encode(b), (a.clamp(0.0, 1.0) * 255.0).round() as u8])
+    })
+}
+
+/// Decodes an sRGB image to linear light, as EXR files are expected to hold.
+pub fn srgb_to_linear(image: &RgbaImage) -> DynamicImage {
+    let (width, height) = image.dimensions();
+    let image = Rgba32FImage::from_fn(width, height, |x, y| {
+        let [r, g, b, a] = image.get_pixel(x, y).0;
+        let decode = |value: u8| {
+            let value = value as f32 / 255.0;
+            if value <= 0.040_45 { value / 12.92 } else { ((value + 0.055) / 1.055).powf(2.4) }
+        };
+        image::Rgba([decode(r), decode(g), decode(b), a as f32 / 255.0])
+    });
+    DynamicImage::ImageRgba32F(image)
+}
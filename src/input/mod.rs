@@ -0,0 +1,152 @@
+use image::{
+    RgbImage,
+    RgbaImage,
+};
+use log::warn;
+use serde::Deserialize;
+use std::{
+    error::Error,
+    path::{
+        Path,
+        PathBuf,
+    },
+};
+use video_rs::{
+    Time,
+    decode::{
+        Decoder,
+        DecoderBuilder,
+    },
+};
+
+use crate::{
+    input::image_sequence::{
+        ImageSequenceSource,
+        SequencePattern,
+    },
+    preprocess::read_rotation,
+};
+
+pub mod image_sequence;
+pub mod raw;
+
+/// Holds the settings of the source the frames are read from.
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct InputSettings {
+    /// The video to process, or a numbered image sequence such as `shot_%05d.png`.
+    pub path: PathBuf,
+    /// The frame rate of an image sequence, which sets the timestamps of its frames.
+    /// Videos use their own timestamps.
+    pub frame_rate: f32,
+}
+
+impl Default for InputSettings {
+    fn default() -> Self {
+        InputSettings {
+            path: PathBuf::from("input.mp4"),
+            frame_rate: 24.0,
+        }
+    }
+}
+
+/// A source of frames to process.
+pub trait FrameSource {
+    /// Returns the size of the frames as they are read, before any preprocessing.
+    fn size(&self) -> (u32, u32);
+
+    /// Returns the average frame rate.
+    fn frame_rate(&self) -> f32;
+
+    /// Returns the number of frames, used for the progress bar.
+    fn frame_count(&self) -> Result<u64, Box<dyn Error>>;
+
+    /// Returns the clockwise rotation the frames should be displayed with.
+    fn rotation(&self) -> Result<u32, Box<dyn Error>>;
+
+    /// Returns the file holding the audio track that belongs to the frames, if there is one.
+    fn audio_path(&self) -> Option<&Path>;
+
+    /// Skips ahead to the frames shown at or before `timestamp_ms`. The first frame read afterwards
+    /// may come earlier, but never later.
+    fn seek(&mut self, timestamp_ms: f64) -> Result<(), Box<dyn Error>>;
+
+    /// Reads the next frame with its timestamp, none at the end of the source.
+    fn next_frame(&mut self) -> Result<Option<(RgbaImage, Time)>, Box<dyn Error>>;
+}
+
+/// Opens the source at `settings.path`. Paths with a frame number pattern are read as an image
+/// sequence, anything else as a video.
+pub fn open_source(settings: &InputSettings) -> Result<Box<dyn FrameSource>, Box<dyn Error>> {
+    match SequencePattern::parse(&settings.path) {
+        Some(pattern) => Ok(Box::new(ImageSequenceSource::open(pattern, settings.frame_rate)?)),
+        None => Ok(Box::new(VideoSource::open(&settings.path)?)),
+    }
+}
+
+/// Decodes the frames of a video file.
+pub struct VideoSource {
+    path: PathBuf,
+    decoder: Decoder,
+}
+
+impl VideoSource {
+    /// Opens the video at `path`.
+    pub fn open(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let decoder = DecoderBuilder::new(path).build()?;
+        Ok(VideoSource { path: path.to_path_buf(), decoder })
+    }
+}
+
+impl FrameSource for VideoSource {
+    fn size(&self) -> (u32, u32) {
+        self.decoder.size()
+    }
+
+    fn frame_rate(&self) -> f32 {
+        self.decoder.frame_rate()
+    }
+
+    fn frame_count(&self) -> Result<u64, Box<dyn Error>> {
+        Ok(self.decoder.frames()?)
+    }
+
+    fn rotation(&self) -> Result<u32, Box<dyn Error>> {
+        read_rotation(&self.path)
+    }
+
+    fn audio_path(&self) -> Option<&Path> {
+        Some(&self.path)
+    }
+
+    fn seek(&mut self, timestamp_ms: f64) -> Result<(), Box<dyn Error>> {
+        // The decoder only accepts a keyframe within a second of its target, so aiming a second
+        // early lands at or before `timestamp_ms`, but only if a keyframe lies in the two seconds
+        // before it. Otherwise decoding starts over, and the caller skips the frames up to
+        // `timestamp_ms`
+        let target_ms = timestamp_ms - 1000.0;
+        if target_ms <= 0.0 {
+            self.decoder.seek_to_start()?;
+        } else if let Err(e) = self.decoder.seek(target_ms as i64) {
+            warn!(
+                "No keyframe near {:.3} s in '{}' ({}), decoding from the start",
+                timestamp_ms / 1000.0,
+                self.path.display(),
+                e
+            );
+            self.decoder.seek_to_start()?;
+        }
+        Ok(())
+    }
+
+    fn next_frame(&mut self) -> Result<Option<(RgbaImage, Time)>, Box<dyn Error>> {
+        // Decoding stops at the end of the stream or at the first frame that fails to decode
+        let Ok((timestamp, frame)) = self.decoder.decode() else {
+            return Ok(None);
+        };
+        let (width, height) = self.decoder.size();
+        let rgb_frame = RgbImage::from_raw(width, height, frame.into_raw_vec_and_offset().0)
+            .ok_or("Failed to create RGB image from frame")?;
+        Ok(Some((image::DynamicImage::ImageRgb8(rgb_frame).to_rgba8(), timestamp)))
+    }
+}
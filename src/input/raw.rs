@@ -0,0 +1,134 @@
+use image::RgbaImage;
+use serde::Deserialize;
+use std::{
+    error::Error,
+    io::{
+        self,
+        BufReader,
+        Read,
+        Stdin,
+    },
+    path::Path,
+};
+use video_rs::Time;
+
+use crate::input::FrameSource;
+
+/// Defines the pixel layouts of raw video frames.
+#[allow(dead_code)]
+#[derive(Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RawPixelFormat {
+    /// Three bytes per pixel: red, green, blue.
+    Rgb24,
+    /// Four bytes per pixel: red, green, blue, alpha.
+    Rgba,
+}
+
+impl RawPixelFormat {
+    /// Returns the number of bytes of one pixel.
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            RawPixelFormat::Rgb24 => 3,
+            RawPixelFormat::Rgba => 4,
+        }
+    }
+
+    /// Returns the name FFmpeg uses for the format.
+    pub fn ffmpeg_name(self) -> &'static str {
+        match self {
+            RawPixelFormat::Rgb24 => "rgb24",
+            RawPixelFormat::Rgba => "rgba",
+        }
+    }
+
+    /// Converts a frame to raw bytes in this format.
+    pub fn to_bytes(self, frame: RgbaImage) -> Vec<u8> {
+        match self {
+            RawPixelFormat::Rgb24 => image::DynamicImage::ImageRgba8(frame).to_rgb8().into_raw(),
+            RawPixelFormat::Rgba => frame.into_raw(),
+        }
+    }
+}
+
+/// Reads one raw frame of `width` by `height` pixels in `format` from `reader`. Returns none if
+/// the stream ends before the frame starts.
+pub fn read_raw_frame(
+    reader: &mut impl Read,
+    width: u32,
+    height: u32,
+    format: RawPixelFormat,
+) -> Result<Option<RgbaImage>, Box<dyn Error>> {
+    let mut bytes = vec![0; width as usize * height as usize * format.bytes_per_pixel()];
+    let mut filled = 0;
+    while filled < bytes.len() {
+        match reader.read(&mut bytes[filled..])? {
+            0 if filled == 0 => return Ok(None),
+            0 => return Err("The raw video stream ended in the middle of a frame".into()),
+            count => filled += count,
+        }
+    }
+
+    let frame = match format {
+        RawPixelFormat::Rgb24 => {
+            let rgb_frame = image::RgbImage::from_raw(width, height, bytes).ok_or("Failed to create RGB image from frame")?;
+            image::DynamicImage::ImageRgb8(rgb_frame).to_rgba8()
+        }
+        RawPixelFormat::Rgba => RgbaImage::from_raw(width, height, bytes).ok_or("Failed to create RGBA image from frame")?,
+    };
+    Ok(Some(frame))
+}
+
+/// Reads fixed-size raw frames from stdin, shown at a constant frame rate. Unlike the live mode,
+/// every frame is processed, as the program writing them waits for the pipe to drain.
+pub struct RawSource {
+    reader: BufReader<Stdin>,
+    width: u32,
+    height: u32,
+    frame_rate: f32,
+    format: RawPixelFormat,
+    /// The index of the next frame.
+    index: u64,
+}
+
+impl RawSource {
+    /// Creates a source of raw frames of `width` by `height` pixels in `format` on stdin.
+    pub fn stdin(width: u32, height: u32, frame_rate: f32, format: RawPixelFormat) -> Self {
+        RawSource { reader: BufReader::new(io::stdin()), width, height, frame_rate, format, index: 0 }
+    }
+}
+
+impl FrameSource for RawSource {
+    fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn frame_rate(&self) -> f32 {
+        self.frame_rate
+    }
+
+    fn frame_count(&self) -> Result<u64, Box<dyn Error>> {
+        Ok(0)
+    }
+
+    fn rotation(&self) -> Result<u32, Box<dyn Error>> {
+        Ok(0)
+    }
+
+    fn audio_path(&self) -> Option<&Path> {
+        None
+    }
+
+    fn seek(&mut self, _timestamp_ms: f64) -> Result<(), Box<dyn Error>> {
+        Err("Raw input can't seek".into())
+    }
+
+    fn next_frame(&mut self) -> Result<Option<(RgbaImage, Time)>, Box<dyn Error>> {
+        let Some(frame) = read_raw_frame(&mut self.reader, self.width, self.height, self.format)? else {
+            return Ok(None);
+        };
+        let timestamp = Time::from_secs_f64(self.index as f64 / self.frame_rate as f64);
+        self.index += 1;
+        Ok(Some((frame, timestamp)))
+    }
+}
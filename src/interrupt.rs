@@ -0,0 +1,43 @@
+use std::{
+    error::Error,
+    sync::atomic::{
+        AtomicBool,
+        Ordering,
+    },
+};
+
+/// Set once the process is asked to stop, by Ctrl-C or SIGTERM.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Installs the handler of Ctrl-C and SIGTERM, which asks the running renders to stop so they can
+/// remove their temporary files. A second Ctrl-C exits right away.
+pub fn install() -> Result<(), Box<dyn Error>> {
+    let handler = handle as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    for signal in [libc::SIGINT, libc::SIGTERM] {
+        // SAFETY: the handler only touches an atomic and calls `_exit`, which are async-signal-safe
+        if unsafe { libc::signal(signal, handler) } == libc::SIG_ERR {
+            return Err("Failed to install the Ctrl-C handler".into());
+        }
+    }
+    Ok(())
+}
+
+extern "C" fn handle(_signal: libc::c_int) {
+    if INTERRUPTED.swap(true, Ordering::SeqCst) {
+        // SAFETY: `_exit` is async-signal-safe, unlike `std::process::exit`
+        unsafe { libc::_exit(130) };
+    }
+}
+
+/// Returns `true` if the process was asked to stop.
+pub fn is_interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Returns an error if the process was asked to stop.
+pub fn check() -> Result<(), Box<dyn Error>> {
+    if is_interrupted() {
+        return Err("Interrupted".into());
+    }
+    Ok(())
+}
@@ -0,0 +1,274 @@
+use image::RgbaImage;
+use log::{
+    error,
+    info,
+};
+use serde::Deserialize;
+use std::{
+    error::Error,
+    io::{
+        self,
+        Read,
+    },
+    path::{
+        Path,
+        PathBuf,
+    },
+    process::{
+        Child,
+        Command,
+        Stdio,
+    },
+    sync::{
+        Arc,
+        Condvar,
+        Mutex,
+    },
+    thread,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+use video_rs::Time;
+
+use crate::{
+    input::{
+        FrameSource,
+        raw::{
+            RawPixelFormat,
+            read_raw_frame,
+        },
+    },
+    interrupt,
+    output::{
+        FrameSink,
+        raw::RawPipeSink,
+    },
+};
+
+/// How often waiting for a live frame checks whether the process was asked to stop.
+const INTERRUPT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Defines where live frames are captured from.
+#[allow(dead_code)]
+#[derive(Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LiveCapture {
+    /// A V4L2 video device such as a webcam, read through FFmpeg.
+    V4l2,
+    /// Raw frames on stdin, for testing or for feeding frames from another program.
+    Stdin,
+}
+
+/// Defines where live output is shown.
+#[allow(dead_code)]
+#[derive(Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LiveOutput {
+    /// Raw frames on stdout, to pipe into another program.
+    Pipe,
+    /// A local FFplay window.
+    Window,
+}
+
+/// Configuration for the live mode, which runs the effect in real time on captured frames
+/// instead of processing a video file.
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LiveSettings {
+    /// If `true`, frames are captured live instead of read from the input file.
+    pub enabled: bool,
+    pub capture: LiveCapture,
+    /// The V4L2 device to capture from.
+    pub device: PathBuf,
+    /// The width of the captured frames. V4L2 frames are scaled to it if the device doesn't
+    /// provide it.
+    pub width: u32,
+    /// The height of the captured frames.
+    pub height: u32,
+    /// The frame rate requested from the V4L2 device. The effect runs on the actual capture time
+    /// of each frame, whatever the device delivers.
+    pub frame_rate: f32,
+    /// The pixel format of raw frames on stdin.
+    pub pixel_format: RawPixelFormat,
+    pub output: LiveOutput,
+    /// The pixel format of raw frames on stdout.
+    pub output_pixel_format: RawPixelFormat,
+}
+
+impl Default for LiveSettings {
+    fn default() -> Self {
+        LiveSettings {
+            enabled: false,
+            capture: LiveCapture::V4l2,
+            device: PathBuf::from("/dev/video0"),
+            width: 640,
+            height: 480,
+            frame_rate: 30.0,
+            pixel_format: RawPixelFormat::Rgb24,
+            output: LiveOutput::Window,
+            output_pixel_format: RawPixelFormat::Rgb24,
+        }
+    }
+}
+
+impl LiveSettings {
+    /// Creates the sink showing the live frames of `width` by `height` pixels.
+    pub fn create_sink(&self, width: u32, height: u32) -> Result<Box<dyn FrameSink>, Box<dyn Error>> {
+        Ok(match self.output {
+            LiveOutput::Pipe => Box::new(RawPipeSink::stdout(self.output_pixel_format)),
+            LiveOutput::Window => Box::new(RawPipeSink::window(width, height, self.frame_rate)?),
+        })
+    }
+}
+
+/// The newest captured frame, handed from the capture thread to the processing loop.
+#[derive(Default)]
+struct FrameSlot {
+    /// The frame and its capture time in milliseconds since the capture started.
+    frame: Option<(RgbaImage, f64)>,
+    /// Set once the capture ended.
+    is_closed: bool,
+    /// The number of frames replaced before they were processed.
+    dropped: u64,
+}
+
+/// Captures frames on a separate thread. Only the newest frame is kept, so when the effect can't
+/// keep up, frames are dropped instead of queuing up and adding latency.
+pub struct LiveSource {
+    size: (u32, u32),
+    frame_rate: f32,
+    slot: Arc<(Mutex<FrameSlot>, Condvar)>,
+    /// The FFmpeg process capturing from the V4L2 device.
+    ffmpeg: Option<Child>,
+}
+
+impl LiveSource {
+    /// Starts capturing with `settings`.
+    pub fn open(settings: &LiveSettings) -> Result<Self, Box<dyn Error>> {
+        let (width, height) = (settings.width, settings.height);
+        let (reader, format, ffmpeg): (Box<dyn Read + Send>, _, _) = match settings.capture {
+            LiveCapture::Stdin => {
+                info!("Capturing raw {} frames from stdin", settings.pixel_format.ffmpeg_name());
+                (Box::new(io::stdin()), settings.pixel_format, None)
+            }
+            LiveCapture::V4l2 => {
+                info!("Capturing from {}", settings.device.display());
+                let mut ffmpeg = spawn_v4l2_capture(&settings.device, width, height, settings.frame_rate)?;
+                let stdout = ffmpeg.stdout.take().ok_or("Failed to read the output of FFmpeg")?;
+                (Box::new(stdout), RawPixelFormat::Rgb24, Some(ffmpeg))
+            }
+        };
+
+        let slot = Arc::new((Mutex::new(FrameSlot::default()), Condvar::new()));
+        let capture_slot = Arc::clone(&slot);
+        thread::spawn(move || capture_frames(reader, width, height, format, &capture_slot));
+
+        Ok(LiveSource { size: (width, height), frame_rate: settings.frame_rate, slot, ffmpeg })
+    }
+}
+
+impl FrameSource for LiveSource {
+    fn size(&self) -> (u32, u32) {
+        self.size
+    }
+
+    fn frame_rate(&self) -> f32 {
+        self.frame_rate
+    }
+
+    fn frame_count(&self) -> Result<u64, Box<dyn Error>> {
+        Ok(0)
+    }
+
+    fn rotation(&self) -> Result<u32, Box<dyn Error>> {
+        Ok(0)
+    }
+
+    fn audio_path(&self) -> Option<&Path> {
+        None
+    }
+
+    fn seek(&mut self, _timestamp_ms: f64) -> Result<(), Box<dyn Error>> {
+        Err("Live input can't seek".into())
+    }
+
+    fn next_frame(&mut self) -> Result<Option<(RgbaImage, Time)>, Box<dyn Error>> {
+        let (lock, ready) = &*self.slot;
+        let mut slot = lock.lock().map_err(|_| "The capture thread failed")?;
+        // A stalled capture would keep the loop from ever seeing Ctrl-C, so the wait wakes up
+        // regularly to check for it
+        while slot.frame.is_none() && !slot.is_closed {
+            if interrupt::is_interrupted() {
+                return Ok(None);
+            }
+            slot = ready.wait_timeout(slot, INTERRUPT_POLL_INTERVAL).map_err(|_| "The capture thread failed")?.0;
+        }
+        Ok(slot.frame.take().map(|(frame, capture_ms)| (frame, Time::from_secs_f64(capture_ms / 1000.0))))
+    }
+}
+
+impl Drop for LiveSource {
+    fn drop(&mut self) {
+        if let Ok(slot) = self.slot.0.lock()
+            && slot.dropped > 0
+        {
+            info!("Dropped {} frames to keep up with the capture", slot.dropped);
+        }
+        if let Some(ffmpeg) = self.ffmpeg.as_mut() {
+            let _ = ffmpeg.kill();
+            let _ = ffmpeg.wait();
+        }
+    }
+}
+
+/// Starts FFmpeg capturing raw RGB frames of `width` by `height` pixels from a V4L2 device.
+fn spawn_v4l2_capture(device: &Path, width: u32, height: u32, frame_rate: f32) -> Result<Child, Box<dyn Error>> {
+    let size = format!("{}x{}", width, height);
+    let child = Command::new("ffmpeg")
+        .args(["-loglevel", "error", "-f", "v4l2", "-framerate", &frame_rate.to_string(), "-video_size", &size])
+        .arg("-i")
+        .arg(device)
+        .args(["-vf", &format!("scale={}:{}", width, height), "-f", "rawvideo", "-pix_fmt", "rgb24", "-"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Failed to start FFmpeg, which captures from V4L2 devices: {}", e))?;
+    Ok(child)
+}
+
+/// Reads frames until the stream ends, stamping each with the time it arrived and replacing any
+/// frame that wasn't processed yet.
+fn capture_frames(
+    mut reader: Box<dyn Read + Send>,
+    width: u32,
+    height: u32,
+    format: RawPixelFormat,
+    slot: &(Mutex<FrameSlot>, Condvar),
+) {
+    let start = Instant::now();
+    let (lock, ready) = slot;
+    loop {
+        let frame = match read_raw_frame(&mut reader, width, height, format) {
+            Ok(Some(frame)) => frame,
+            Ok(None) => break,
+            Err(e) => {
+                error!("Live capture stopped: {}", e);
+                break;
+            }
+        };
+        let capture_ms = start.elapsed().as_secs_f64() * 1000.0;
+        let Ok(mut slot) = lock.lock() else {
+            return;
+        };
+        if slot.frame.replace((frame, capture_ms)).is_some() {
+            slot.dropped += 1;
+        }
+        ready.notify_one();
+    }
+    if let Ok(mut slot) = lock.lock() {
+        slot.is_closed = true;
+    }
+    ready.notify_one();
+}
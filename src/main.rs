@@ -1,225 +1,635 @@
-use image::{
-    RgbImage,
-    RgbaImage,
-};
+use image::RgbaImage;
 use indicatif::{
+    MultiProgress,
     ProgressBar,
+    ProgressDrawTarget,
     ProgressStyle,
 };
-use log::{error, info};
+use log::{error, info, warn};
 use std::{
-    fs,
+    error::Error,
     path::Path,
     process::Command,
 };
-use video_rs::{
-    Frame,
-    decode::DecoderBuilder,
-    encode::{
-        Encoder,
-        Settings,
-    },
-};
 
-use crate::video_processors::{
-    CalculatedDecay,
-    EffectMode,
-    EffectSettings,
-    blended::{
-        BlendedSettings,
-        process_blended_frame,
+use crate::{
+    checkpoint::{
+        Checkpointer,
+        RenderState,
     },
-    colored::{
-        ColoredSettings,
-        process_colored_frame,
+    cli::{
+        Args,
+        RawVideo,
+        USAGE,
     },
-    priority::{
-        PriorityMode,
-        PrioritySettings,
-        process_priority_frame,
+    config::{
+        CONFIG_PATH,
+        Config,
     },
-    stable::{
-        StableSettings,
-        process_stable_frame,
+    input::{
+        FrameSource,
+        image_sequence::SequencePattern,
+        open_source,
+        raw::RawSource,
+    },
+    live::LiveSource,
+    output::{
+        FrameSink,
+        OutputLayer,
+        OverwritePolicy,
+        SplitVideoSink,
+        VideoSink,
+        alpha_video::{
+            AlphaCodec,
+            AlphaVideoSink,
+        },
+        animation::AnimationFormat,
+        check_video_path,
+        image_sequence::ImageSequenceSink,
+        resample::{
+            FrameRateConversion,
+            FrameResampler,
+        },
+        raw::RawPipeSink,
+        stills::{
+            NullSink,
+            StillSink,
+            StillSource,
+        },
+        temp_file::TempFile,
+    },
+    preprocess::Preprocessor,
+    video_processors::{
+        audio::AudioEnvelopes,
+        segments::{
+            active_segment,
+            mix_frames,
+        },
+        stack::EffectStack,
+        utils::extract_trail_layer,
     },
 };
 
+mod batch;
+mod checkpoint;
+mod cli;
+mod config;
+mod input;
+mod interrupt;
+mod live;
+mod output;
+mod preprocess;
+mod preview;
 mod video_processors;
+mod watch;
 
 fn main() {
 
     // Initialize logger
+    // Logs go to stderr, which keeps stdout free for raw video.
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"))
         .format_timestamp(None)
+        .target(env_logger::Target::Stderr)
         .init();
+
+    // Ctrl-C stops the renders cleanly, removing their temporary files
+    if let Err(e) = interrupt::install() {
+        warn!("{}", e);
+    }
+
+    let args = match Args::parse() {
+        Ok(args) => args,
+        Err(e) => {
+            error!("{}", e);
+            eprintln!("{}", USAGE);
+            return;
+        }
+    };
+    if args.help {
+        println!("{}", USAGE);
+        return;
+    }
+    let raw = args.raw;
+    let resume = args.resume;
     
     // Effect Settings
-    let settings = EffectSettings {
-        // General
-        mode: EffectMode::Priority,
-        preserve_audio: true,
-        motion_threshold_percent: 0.1,
-        use_edge_correction: false,
-        n_frames_step: 1,
-
-        // Mode-Specific
-        stable: StableSettings {
-            burn_in_factor: 1.0,
-            tracer_duration_ms: None,
-        },
-        blended: BlendedSettings {
-            blend_factor: 0.5,
-            tracer_duration_ms: Some(5000),
-        },
-        colored: ColoredSettings {
-            color: image::Rgba([255, 255, 255, 255]),
-            rainbow_mode: true,
-            rainbow_speed: 5.0,
-            tracer_opacity: 1.0,
-            tracer_duration_ms: Some(5000),
-        },
-        priority: PrioritySettings {
-            mode: PriorityMode::Lightest,
-            tracer_duration_ms: Some(5000),
-        },
+    // Loaded from the config file if there is one, otherwise the defaults are used.
+    let mut config = match Config::load(Path::new(CONFIG_PATH)) {
+        Ok(config) => config,
+        Err(e) => {
+            error!("Failed to load the config: {}", e);
+            return;
+        }
     };
+    if config.preview.enabled {
+        config.preview.scale_effect(&mut config.effect, &mut config.keyframes);
+    }
+
+    if raw.is_some()
+        && (config.preview.enabled
+            || config.live.enabled
+            || config.batch.is_enabled()
+            || config.watch.is_enabled()
+            || config.stills.only)
+    {
+        error!("Raw video can't be used with the preview, live, batch or watch mode, or with stills only");
+        return;
+    }
 
     // Init video-rs
-    if let Err(e) = video_rs::init() {
+    // Raw video is read and written without it.
+    if raw.is_none()
+        && let Err(e) = video_rs::init()
+    {
         error!("Failed to initialize video_rs: {}", e);
         return;
     }
 
-    let source_path = Path::new("input.mp4");
-    let temp_video_path = Path::new("temp_output.mp4");
-    let final_output_path = Path::new("output.mp4");
+    // Batch Processing
+    // Every clip of a batch is rendered like a single video, with its own input and output.
+    if config.batch.is_enabled() {
+        // Clips with a checkpoint are resumed, the others start from the beginning
+        let render_clip = |clip_config: &Config, progress: &MultiProgress| {
+            let resume = resume && Checkpointer::exists(&clip_config.output.path);
+            render(clip_config, None, resume, Some(progress))
+        };
+        if let Err(e) = batch::run(&config, render_clip) {
+            error!("Batch processing failed: {}", e);
+            // Scripts running the batch can only tell that clips failed from the exit code
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // Watch Mode
+    // Runs until the process is stopped, rendering every video that appears in the directory.
+    if config.watch.is_enabled() {
+        if let Err(e) = watch::run(&config, |job_config, resume| render(job_config, None, resume, None)) {
+            error!("Watching stopped: {}", e);
+        }
+        return;
+    }
+
+    if let Err(e) = render(&config, raw, resume, None) {
+        error!("An error occurred during video processing: {}", e);
+    }
+}
+
+/// Renders the input of `config`, or `raw` video if set, continuing from the last checkpoint if
+/// `resume` is `true`. The progress bar is added to `progress` if several videos are rendered at
+/// once.
+fn render(
+    config: &Config,
+    raw: Option<RawVideo>,
+    resume: bool,
+    progress: Option<&MultiProgress>,
+) -> Result<(), Box<dyn Error>> {
+    let settings = &config.effect;
+    let preview = config.preview.enabled.then_some(&config.preview);
+    let source_path = config.input.path.as_path();
+    let final_output_path = config.output.path.as_path();
+    // Image sequences and animations are written directly, without a temporary video or an audio
+    // track
+    let output_pattern = SequencePattern::parse(final_output_path);
+    let animation_format = AnimationFormat::from_path(final_output_path).filter(|_| output_pattern.is_none());
+    // The trail layer is written with alpha, which the preview formats don't keep
+    let has_alpha = config.output.layer == OutputLayer::Trails && preview.is_none();
+    let alpha_codec = AlphaCodec::from_path(final_output_path)
+        .filter(|_| has_alpha && output_pattern.is_none() && animation_format.is_none());
+    // Stills are only saved from full renders, which can skip the video entirely
+    let stills = config.stills.is_enabled().then_some(&config.stills).filter(|_| preview.is_none());
+    let is_stills_only = stills.is_some_and(|stills| stills.only);
+    let live = config.live.enabled.then_some(&config.live);
+    let is_video_output = output_pattern.is_none()
+        && animation_format.is_none()
+        && alpha_codec.is_none()
+        && !is_stills_only
+        && live.is_none()
+        && raw.is_none();
+    let writes_output = preview.is_none() && !is_stills_only && live.is_none() && raw.is_none();
+    // A single PNG can't hold a video, and an animation is asked for with `.apng`
+    if writes_output
+        && output_pattern.is_none()
+        && final_output_path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("png"))
+    {
+        return Err(format!(
+            "{} has no frame number, use a pattern such as frame_%05d.png for a PNG sequence or .apng for an animation",
+            final_output_path.display()
+        )
+        .into());
+    }
+    // video-rs only writes MP4, which would otherwise end up under any other extension
+    if writes_output && is_video_output {
+        check_video_path(final_output_path)?;
+    }
+    // Checkpoints split the video into parts, which only works for a video of every frame
+    let checkpoint_interval = config.checkpoint.interval_secs.filter(|_| preview.is_none());
+    if checkpoint_interval.is_some() && (!is_video_output || stills.is_some() || config.output.frame_rate.is_some()) {
+        return Err("Checkpoints need a video output, without stills or an output frame rate".into());
+    }
+    if resume && checkpoint_interval.is_none() {
+        return Err("--resume needs checkpoints, set `checkpoint.interval_secs`".into());
+    }
+
+    // The preview is written next to the output, named after it
+    let preview_path = preview.map(|preview| preview.output_path(final_output_path));
+
+    // Refuse to replace an existing output before spending any time on the frames
+    if config.output.overwrite == OverwritePolicy::NoClobber {
+        let existing = match (&preview_path, &output_pattern) {
+            (Some(preview_path), _) => preview_path.exists().then_some(preview_path.as_path()),
+            (None, _) if !writes_output => None,
+            (None, Some(pattern)) => {
+                pattern.find_frames().is_ok_and(|frames| !frames.is_empty()).then_some(final_output_path)
+            }
+            (None, None) => final_output_path.exists().then_some(final_output_path),
+        };
+        if let Some(existing) = existing {
+            return Err(format!("{} already exists, and the overwrite policy is no_clobber", existing.display()).into());
+        }
+    }
+    interrupt::check()?;
+
+    // Single files are written next to the output under a unique temporary name, and only moved
+    // into place once complete. The temporary files are removed if the render fails.
+    let output_extension = final_output_path.extension().and_then(|extension| extension.to_str()).unwrap_or("mp4");
+    let temp_output = TempFile::next_to(final_output_path, output_extension);
+    // video-rs writes an MP4, which FFmpeg remuxes with the audio
+    let temp_video = TempFile::next_to(final_output_path, "mp4");
+    let temp_preview = preview
+        .zip(preview_path.as_deref())
+        .map(|(preview, preview_path)| TempFile::next_to(preview_path, preview.format.extension()));
+
+    let mut checkpointer: Option<Checkpointer> = None;
 
     // Video Processing
     // This block handles the decoding, encoding and processing, needs to be in a separate scope for audio handling later.
-    if let Err(e) = (|| -> Result<(), Box<dyn std::error::Error>> {
+    {
         // Decoding
-        info!("Opening decoder for: {}", source_path.display());
-        let mut decoder = DecoderBuilder::new(source_path).build()?;
-        let (width, height) = decoder.size();
-        let frame_rate = decoder.frame_rate();
-        let total_frames = decoder.frames()?;
-        info!("Video properties: {}x{} @ {} fps", width, height, frame_rate);
-
-        // Calculate the per-frame decay amount for each effect mode based on the configured duration.
-        let decay = CalculatedDecay {
-            stable: duration_ms_to_decay(settings.stable.tracer_duration_ms, frame_rate),
-            blended: duration_ms_to_decay(settings.blended.tracer_duration_ms, frame_rate),
-            colored: duration_ms_to_decay(settings.colored.tracer_duration_ms, frame_rate),
-            priority: duration_ms_to_decay(settings.priority.tracer_duration_ms, frame_rate),
+        let mut source: Box<dyn FrameSource> = match (raw, live) {
+            (Some(raw), _) => {
+                info!("Reading raw {} frames from stdin", raw.pixel_format.ffmpeg_name());
+                Box::new(RawSource::stdin(raw.width, raw.height, raw.frame_rate, raw.pixel_format))
+            }
+            (None, Some(live)) => Box::new(LiveSource::open(live)?),
+            (None, None) => {
+                info!("Opening source: {}", source_path.display());
+                open_source(&config.input)?
+            }
         };
+        let (decoded_width, decoded_height) = source.size();
+        let frame_rate = source.frame_rate();
+        let mut total_frames = source.frame_count()?;
+        info!("Video properties: {}x{} @ {} fps", decoded_width, decoded_height, frame_rate);
 
-        // Create encoder
-        let mut encoder = Encoder::new(
-            temp_video_path,
-            Settings::preset_h264_yuv420p(width as usize, height as usize, false),
-        )?;
+        // A preview only decodes its time window
+        if let Some(preview) = preview {
+            if preview.start_ms > 0.0 {
+                source.seek(preview.start_ms)?;
+            }
+            if let Some(duration_ms) = preview.duration_ms {
+                total_frames = total_frames.min((duration_ms / 1000.0 * frame_rate as f64).ceil() as u64);
+            }
+        }
+
+        // Resolve the rotation, crop and scale, which set the size of the processed frames
+        let rotation = match config.preprocess.rotation {
+            Some(rotation) => rotation,
+            None => source.rotation()?,
+        };
+        let mut preprocessor = Preprocessor::new(&config.preprocess, (decoded_width, decoded_height), rotation)?;
+        if let Some(preview) = preview {
+            preprocessor = preprocessor.scaled(preview.scale);
+        }
+        let (width, height) = preprocessor.output_size();
+        if (width, height) != (decoded_width, decoded_height) || rotation != 0 {
+            info!("Preprocessing to {}x{} (rotated by {} degrees)", width, height, rotation);
+        }
+
+        // Continue from the last checkpoint, or start saving new ones
+        let mut restored_state = None;
+        if let Some(interval_secs) = checkpoint_interval {
+            checkpointer = Some(if resume {
+                let (resumed, state) = Checkpointer::resume(final_output_path, source_path, (width, height), interval_secs)?;
+                restored_state = Some(state);
+                resumed
+            } else {
+                Checkpointer::start(final_output_path, source_path, (width, height), interval_secs)?
+            });
+        }
+
+        // Create the sink the processed frames are written to
+        let sink: Box<dyn FrameSink> = match preview.zip(temp_preview.as_ref()) {
+            Some((preview, temp_preview)) => {
+                info!("Rendering a preview to {}", preview.output_path(final_output_path).display());
+                preview.create_sink(temp_preview.path(), width, height, &config.output.animation)?
+            }
+            None if is_stills_only => Box::new(NullSink),
+            None if let Some(raw) = raw => Box::new(RawPipeSink::stdout(raw.pixel_format)),
+            None if let Some(live) = live => live.create_sink(width, height)?,
+            None => match (output_pattern, animation_format, alpha_codec) {
+                (Some(pattern), _, _) => Box::new(ImageSequenceSink::new(
+                    pattern,
+                    config.output.bit_depth,
+                    config.output.start_number,
+                    has_alpha,
+                )?),
+                (None, Some(format), _) => config.output.animation.create_sink(temp_output.path(), format, has_alpha)?,
+                (None, None, Some(codec)) => {
+                    // FFmpeg encodes at a constant frame rate, so the frames are resampled to the
+                    // source frame rate unless an output frame rate is set
+                    let output_frame_rate = config.output.frame_rate.unwrap_or(frame_rate);
+                    let sink = Box::new(AlphaVideoSink::new(temp_output.path(), codec, width, height, output_frame_rate)?);
+                    match config.output.frame_rate {
+                        Some(_) => sink,
+                        None => Box::new(FrameResampler::new(sink, frame_rate, FrameRateConversion::Nearest)),
+                    }
+                }
+                (None, None, None) if has_alpha => {
+                    return Err("The trail layer needs an output with alpha: a .mov or .webm video, an APNG or WebP, or a PNG, TIFF or EXR sequence".into());
+                }
+                (None, None, None) => match &checkpointer {
+                    Some(checkpointer) => {
+                        Box::new(SplitVideoSink::new(checkpointer.dir(), checkpointer.parts(), width, height))
+                    }
+                    None => Box::new(VideoSink::new(temp_video.path(), width, height)?),
+                },
+            },
+        };
+
+        // Analyze the audio track for the audio-driven settings
+        let audio_envelopes = if config.audio.is_empty() {
+            None
+        } else {
+            info!("Analyzing the audio track");
+            let audio_path = source.audio_path().ok_or("Audio modulation needs a video input with an audio track")?;
+            Some(AudioEnvelopes::analyze(audio_path, &config.audio)?)
+        };
+
+        // Convert to the output frame rate if one is set
+        let sink: Box<dyn FrameSink> = match config.output.frame_rate {
+            Some(frame_rate) => {
+                info!("Converting to {} fps", frame_rate);
+                Box::new(FrameResampler::new(sink, frame_rate, config.output.frame_rate_conversion))
+            }
+            None => sink,
+        };
+
+        // Save the requested stills of the processed frames, or of the canvas, which is saved
+        // from the loop only when a still is due
+        let mut canvas_stills = None;
+        let mut sink: Box<dyn FrameSink> = match stills {
+            Some(stills) if stills.source == StillSource::Canvas => {
+                canvas_stills = Some(StillSink::new(Box::new(NullSink), stills, config.output.bit_depth, has_alpha)?);
+                sink
+            }
+            Some(stills) => Box::new(StillSink::new(sink, stills, config.output.bit_depth, has_alpha)?),
+            None => sink,
+        };
+
+        // Only the trails are written if the trail layer is selected
+        let to_output = |source_frame: &RgbaImage, output_frame: RgbaImage| {
+            if has_alpha { extract_trail_layer(source_frame, &output_frame) } else { output_frame }
+        };
 
         // Frame Processing Loop
-        let mut canvas: Option<RgbaImage> = None;
+        let mut stack: Option<EffectStack> = None;
+        // The stack of the segment that ended last, whose canvas is shown until a new one has run
+        let mut ended_stack: Option<EffectStack> = None;
         let mut previous_frame: Option<RgbaImage> = None;
-        let mut rainbow_hue: f32 = 0.0;
+        let mut active_segment_index: Option<usize> = None;
+        let mut frame_index = 0;
+        let mut last_timestamp_ms = 0.0;
+        // A resumed render skips the frames before its checkpoint, and continues with its state
+        let mut restored_stack_states = None;
+        let mut resume_ms = None;
+        let mut is_resume_checked = false;
+        if let Some(state) = restored_state {
+            info!("Resuming at frame {} ({:.1} s)", state.frame_count, state.timestamp_ms / 1000.0);
+            source.seek(state.timestamp_ms)?;
+            frame_index = state.frame_count;
+            last_timestamp_ms = state.timestamp_ms;
+            resume_ms = Some(state.timestamp_ms);
+            active_segment_index = state.active_segment;
+            previous_frame = state.previous_frame;
+            restored_stack_states = state.stack_states;
+        }
 
         // Progress Bar Setup
-        let pb = ProgressBar::new(total_frames as u64);
-        pb.set_style(
-            ProgressStyle::default_bar()
-                .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} frames ({eta})")?
-                .progress_chars("#+-"),
-        );
-
-        for (frame_index, frame_result) in decoder.decode_iter().enumerate() {
-            if let Ok((timestamp, frame)) = frame_result {
-                let rgb_frame: RgbImage =
-                    RgbImage::from_raw(width, height, frame.into_raw_vec_and_offset().0)
-                        .ok_or("Failed to create RGB image from frame")?;
-                let current_frame_image: RgbaImage =
-                    image::DynamicImage::ImageRgb8(rgb_frame).to_rgba8();
-
-                // Initialize the canvas and previous_frame on the first frame
-                if frame_index == 0 {
-                    canvas = Some(if settings.mode == EffectMode::Colored {
-                        RgbaImage::new(width, height)
-                    } else {
-                        current_frame_image.clone()
-                    });
-                    previous_frame = Some(current_frame_image.clone());
-                }
+        // Live and raw input have no known length, so they only count the frames. The progress is
+        // drawn to stderr, like the logs.
+        let pb = if live.is_some() || raw.is_some() {
+            let pb = ProgressBar::with_draw_target(None, ProgressDrawTarget::stderr());
+            pb.set_style(ProgressStyle::default_spinner().template("{prefix}{spinner:.green} [{elapsed_precise}] {pos} frames")?);
+            pb
+        } else {
+            let pb = ProgressBar::with_draw_target(Some(total_frames), ProgressDrawTarget::stderr());
+            pb.set_style(
+                ProgressStyle::default_bar()
+                    .template("{prefix}{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} frames ({eta})")?
+                    .progress_chars("#+-"),
+            );
+            pb
+        };
+        // Batch clips show one bar each, named after their input
+        let pb = match progress {
+            Some(progress) => {
+                let pb = progress.add(pb);
+                let name = source_path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+                pb.set_prefix(format!("{} ", name));
+                pb
+            }
+            None => pb,
+        };
+        pb.set_position(frame_index as u64);
 
-                let canvas_data = canvas.as_mut().unwrap();
-                let prev_frame_data = previous_frame.as_ref().unwrap();
-                let should_update_canvas =
-                    frame_index > 0 && frame_index % settings.n_frames_step == 0;
-
-                // Effect Processing
-                let output_frame = match settings.mode {
-                    EffectMode::Stable => process_stable_frame(
-                        canvas_data,
-                        &current_frame_image,
-                        prev_frame_data,
-                        &settings,
-                        &decay,
-                        should_update_canvas,
-                    ),
-                    EffectMode::Blended => process_blended_frame(
-                        canvas_data,
-                        &current_frame_image,
-                        prev_frame_data,
-                        &settings,
-                        &decay,
-                        should_update_canvas,
-                    ),
-                    EffectMode::Colored => process_colored_frame(
-                        canvas_data,
-                        &current_frame_image,
-                        prev_frame_data,
-                        &settings,
-                        &decay,
-                        should_update_canvas,
-                        &mut rainbow_hue,
-                    ),
-                    EffectMode::Priority => process_priority_frame(
-                        canvas_data,
-                        &current_frame_image,
-                        &settings,
-                        &decay,
-                        should_update_canvas,
-                    ),
+        loop {
+            // Ctrl-C ends a stream like its end would, but abandons a file
+            let is_interrupted = interrupt::is_interrupted();
+            if is_interrupted && (live.is_some() || raw.is_some()) {
+                break;
+            }
+
+            // Save a checkpoint at regular intervals, and before stopping at Ctrl-C
+            if let Some(checkpointer) = &mut checkpointer
+                && (checkpointer.is_due(frame_index) || (is_interrupted && checkpointer.has_new_frames(frame_index)))
+            {
+                let state = RenderState {
+                    frame_count: frame_index,
+                    timestamp_ms: last_timestamp_ms,
+                    active_segment: active_segment_index,
+                    previous_frame: previous_frame.clone(),
+                    stack_states: stack.as_ref().map(EffectStack::states),
                 };
+                checkpointer.save(sink.as_mut(), &state)?;
+            }
+            if is_interrupted {
+                if checkpointer.is_some() {
+                    return Err("Interrupted, continue with --resume".into());
+                }
+                return Err("Interrupted".into());
+            }
 
-                previous_frame = Some(current_frame_image);
+            let Some((frame, timestamp)) = source.next_frame()? else {
+                break;
+            };
+            let timestamp_ms = timestamp.as_secs_f64() * 1000.0;
+            if let Some(checkpoint_ms) = resume_ms {
+                // A seek landing after the checkpoint would leave the frames in between out of the
+                // output, so only the first frame after it is checked
+                if !is_resume_checked && timestamp_ms > checkpoint_ms + 1e-3 {
+                    return Err(format!(
+                        "The input can't be sought back to the checkpoint at {:.3} s, the first frame after seeking is at {:.3} s",
+                        checkpoint_ms / 1000.0,
+                        timestamp_ms / 1000.0,
+                    ).into());
+                }
+                is_resume_checked = true;
+                if timestamp_ms <= checkpoint_ms + 1e-3 {
+                    continue;
+                }
+                resume_ms = None;
+            }
+            if let Some(preview) = preview {
+                if timestamp_ms < preview.start_ms {
+                    continue;
+                }
+                if preview.end_ms().is_some_and(|end_ms| timestamp_ms >= end_ms) {
+                    break;
+                }
+            }
+
+            let current_frame_image: RgbaImage = preprocessor.apply(frame);
 
-                // Encode the processed frame
-                let rgb_output = image::DynamicImage::ImageRgba8(output_frame).to_rgb8();
-                let frame_to_encode: Frame =
-                    Frame::from_shape_vec((height as usize, width as usize, 3), rgb_output.into_raw())
-                        .expect("Could not create ndarray from image buffer");
-                encoder.encode(&frame_to_encode, timestamp)?;
+            // Evaluate the keyframed and audio-driven settings at this frame's timestamp
+            let mut frame_settings = settings.at_time(&config.keyframes, timestamp_ms);
+            if let Some(audio_envelopes) = &audio_envelopes {
+                audio_envelopes.modulate(&config.audio, &mut frame_settings, timestamp_ms);
+            }
 
+            // Find the segment the frame is in, frames outside of all segments pass through
+            let segment_index = active_segment(&config.segments, timestamp_ms);
+            if !config.segments.is_empty() && segment_index.is_none() {
+                if let Some(stack) = stack.take() {
+                    ended_stack = Some(stack);
+                }
+                restored_stack_states = None;
+                sink.write(to_output(&current_frame_image, current_frame_image.clone()), timestamp)?;
+                previous_frame = Some(current_frame_image);
+                frame_index += 1;
+                last_timestamp_ms = timestamp_ms;
                 pb.inc(1);
-            } else {
-                break;
+                continue;
+            }
+            let segment = segment_index.map(|index| &config.segments[index]);
+            let segment_mode = segment.and_then(|segment| segment.mode);
+            if let Some(mode) = segment_mode {
+                frame_settings.mode = mode;
             }
+            let settings = &frame_settings;
+
+            // Every segment starts with a fresh effect state
+            if segment_index != active_segment_index {
+                if let Some(stack) = stack.take() {
+                    ended_stack = Some(stack);
+                }
+                restored_stack_states = None;
+                active_segment_index = segment_index;
+            }
+
+            // Run the configured stack, or only the selected effect if there is none or the
+            // segment overrides the mode
+            let stack = stack.get_or_insert_with(|| {
+                if config.stack.is_empty() || segment_mode.is_some() {
+                    EffectStack::single(settings.mode)
+                } else {
+                    EffectStack::new(config.stack.clone())
+                }
+            });
+            if let Some(states) = restored_stack_states.take() {
+                stack.restore(states)?;
+            }
+            // Stills due between the last frame and this one show the canvas before it
+            if let Some(canvas_stills) = &mut canvas_stills
+                && canvas_stills.is_due_before(timestamp_ms)
+                && let Some(canvas) = stack.canvas().or_else(|| ended_stack.as_ref().and_then(EffectStack::canvas))
+            {
+                canvas_stills.save_before(&canvas, timestamp_ms)?;
+            }
+            let previous_source = previous_frame.as_ref().unwrap_or(&current_frame_image);
+            let is_update_frame = frame_index % settings.n_frames_step == 0;
+
+            // Effect Processing
+            let output_frame = stack.process(
+                &current_frame_image,
+                previous_source,
+                settings,
+                is_update_frame,
+                timestamp_ms,
+            );
+            ended_stack = None;
+            if let Some(canvas_stills) = &mut canvas_stills
+                && canvas_stills.is_due(timestamp_ms)
+                && let Some(canvas) = stack.canvas()
+            {
+                canvas_stills.save_at(&canvas, timestamp_ms)?;
+            }
+            let weight = segment.map_or(1.0, |segment| segment.weight_at(timestamp_ms));
+            let output_frame = mix_frames(&current_frame_image, output_frame, weight);
+
+            // Write the processed frame
+            sink.write(to_output(&current_frame_image, output_frame), timestamp)?;
+
+            previous_frame = Some(current_frame_image);
+            frame_index += 1;
+            last_timestamp_ms = timestamp_ms;
+
+            pb.inc(1);
+        }
+        sink.finish()?;
+        if let Some(canvas_stills) = &canvas_stills {
+            let canvas = stack.as_ref().or(ended_stack.as_ref()).and_then(EffectStack::canvas);
+            canvas_stills.save_end(canvas.as_ref())?;
         }
         pb.finish_with_message("Video processing complete.");
-        Ok(())
-    })() { // Execute the closure
-        error!("An error occurred during video processing: {}", e);
-        return;
+    }
+
+    if let (Some(temp_preview), Some(preview_path)) = (temp_preview, &preview_path) {
+        temp_preview.persist(config.output.overwrite)?;
+        info!("Done! Preview saved to {}", preview_path.display());
+        return Ok(());
+    }
+    if live.is_some() || raw.is_some() {
+        info!("Done! The input stream ended");
+        return Ok(());
+    }
+    if is_stills_only {
+        info!("Done! Stills saved next to {}", config.stills.path.display());
+        return Ok(());
+    }
+    if !is_video_output {
+        // Image sequences are written frame by frame, animations and videos with alpha are moved
+        // into place
+        if animation_format.is_some() || alpha_codec.is_some() {
+            temp_output.persist(config.output.overwrite)?;
+        }
+        info!("Done! Output saved to {}", final_output_path.display());
+        return Ok(());
+    }
+
+    // Join the parts of a checkpointed render into the temporary video
+    if let Some(checkpointer) = &checkpointer {
+        checkpointer.concat(temp_video.path())?;
     }
 
     // Audio Processing
     // If preserve_audio is enabled, use FFmpeg to copy the audio from the source video
-    // to the processed video.
-    if settings.preserve_audio {
+    // to the processed video. Image sequences have no audio to copy.
+    if settings.preserve_audio && SequencePattern::parse(source_path).is_none() {
         let status = Command::new("ffmpeg")
-            .arg("-y")
+            .arg("-n")
             .arg("-i")
-            .arg(temp_video_path)
+            .arg(temp_video.path())
             .arg("-i")
             .arg(source_path)
             .arg("-c:v")
@@ -230,44 +640,29 @@ fn main() {
             .arg("0:v:0")
             .arg("-map")
             .arg("1:a:0")
-            .arg(final_output_path)
+            .arg(temp_output.path())
             .status(); // Well, this is a bit ugly but video_rs doesn't handle audio yet.
 
+        // The temporary video is removed once it goes out of scope
         match status {
-            Ok(s) if s.success() => {
-                // Clean up the temporary video file
-                if let Err(e) = fs::remove_file(temp_video_path) {
-                    error!("Failed to remove temporary file: {}", e);
-                }
-            }
+            Ok(s) if s.success() => temp_output.persist(config.output.overwrite)?,
             _ => {
+                // FFmpeg also receives Ctrl-C, which isn't a reason to keep the video
+                interrupt::check()?;
                 error!("FFmpeg command failed. The video was saved without audio.");
-                if let Err(e) = fs::rename(temp_video_path, final_output_path) {
-                    error!("Failed to rename temporary file: {}", e);
-                }
+                temp_video.persist(config.output.overwrite)?;
             }
         }
     } else {
-        // No audio preservation, just rename the temp file to final output
-        if let Err(e) = fs::rename(temp_video_path, final_output_path) {
-            error!("Failed to rename temporary file: {}", e);
-        }
+        // No audio preservation, just move the temp file to the final output
+        temp_video.persist(config.output.overwrite)?;
     }
 
-    info!("Done! Final video saved to {}", final_output_path.display());
-}
-
-/// Converts a duration in milliseconds to a per-frame decay amount.
-fn duration_ms_to_decay(duration_ms: Option<u32>, frame_rate: f32) -> f32 {
-    match duration_ms {
-        None => 0.0, // No decay
-        Some(0) => 255.0, // Instant decay
-        Some(ms) => {
-            if frame_rate < 1.0 {
-                return 255.0; // Avoid division by zero or very small numbers
-            }
-            let total_frames = (ms as f32 / 1000.0) * frame_rate;
-            255.0 / total_frames
-        }
+    // The checkpoint is no longer needed once the output is in place
+    if let Some(checkpointer) = checkpointer {
+        checkpointer.remove()?;
     }
+
+    info!("Done! Final video saved to {}", final_output_path.display());
+    Ok(())
 }
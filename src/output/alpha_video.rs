@@ -0,0 +1,101 @@
+use image::RgbaImage;
+use std::{
+    error::Error,
+    io::Write,
+    path::{
+        Path,
+        PathBuf,
+    },
+    process::{
+        Child,
+        ChildStdin,
+        Command,
+        Stdio,
+    },
+};
+use video_rs::Time;
+
+use crate::output::FrameSink;
+
+/// Defines the video codecs that keep an alpha channel.
+#[derive(Clone, Copy, PartialEq)]
+pub enum AlphaCodec {
+    /// ProRes 4444 in a QuickTime `.mov`, the usual choice for editing apps.
+    ProRes4444,
+    /// VP9 with alpha in a WebM `.webm`, smaller and playable in browsers.
+    Vp9,
+}
+
+impl AlphaCodec {
+    /// Returns the codec written for the extension of `path`, if it has one that keeps alpha.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "mov" => Some(AlphaCodec::ProRes4444),
+            "webm" => Some(AlphaCodec::Vp9),
+            _ => None,
+        }
+    }
+
+    /// Returns the FFmpeg arguments selecting the codec and its pixel format.
+    fn ffmpeg_args(self) -> &'static [&'static str] {
+        match self {
+            AlphaCodec::ProRes4444 => &["-c:v", "prores_ks", "-profile:v", "4444", "-pix_fmt", "yuva444p10le"],
+            AlphaCodec::Vp9 => &["-c:v", "libvpx-vp9", "-pix_fmt", "yuva420p", "-crf", "30", "-b:v", "0", "-auto-alt-ref", "0"],
+        }
+    }
+}
+
+/// Encodes RGBA frames with alpha by piping them to FFmpeg, as video-rs only encodes H.264
+/// without alpha. The frames are encoded at a constant `frame_rate`, so variable frame rate input
+/// needs to be resampled first.
+pub struct AlphaVideoSink {
+    path: PathBuf,
+    ffmpeg: Child,
+    stdin: Option<ChildStdin>,
+}
+
+impl AlphaVideoSink {
+    /// Starts FFmpeg writing a video to `path` from frames of `width` by `height` pixels. An
+    /// existing file at `path` is never overwritten.
+    pub fn new(path: &Path, codec: AlphaCodec, width: u32, height: u32, frame_rate: f32) -> Result<Self, Box<dyn Error>> {
+        let mut ffmpeg = Command::new("ffmpeg")
+            .args(["-n", "-loglevel", "error", "-f", "rawvideo", "-pix_fmt", "rgba"])
+            .args(["-s", &format!("{}x{}", width, height), "-r", &frame_rate.to_string(), "-i", "-"])
+            .args(codec.ffmpeg_args())
+            .arg(path)
+            .stdin(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Failed to start FFmpeg, which is needed for video with alpha: {}", e))?;
+        let stdin = ffmpeg.stdin.take();
+        Ok(AlphaVideoSink { path: path.to_path_buf(), ffmpeg, stdin })
+    }
+}
+
+impl FrameSink for AlphaVideoSink {
+    fn write(&mut self, frame: RgbaImage, _timestamp: Time) -> Result<(), Box<dyn Error>> {
+        let stdin = self.stdin.as_mut().ok_or("The video was already finished")?;
+        stdin.write_all(frame.as_raw())?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Box<dyn Error>> {
+        // Closing the pipe ends the input, then FFmpeg finishes the file
+        drop(self.stdin.take());
+        let status = self.ffmpeg.wait()?;
+        if !status.success() {
+            return Err(format!("FFmpeg failed to encode {}", self.path.display()).into());
+        }
+        Ok(())
+    }
+}
+
+impl Drop for AlphaVideoSink {
+    fn drop(&mut self) {
+        // Stop FFmpeg if the render ended before `finish`, so it doesn't keep writing the
+        // temporary file after it is removed
+        drop(self.stdin.take());
+        let _ = self.ffmpeg.kill();
+        let _ = self.ffmpeg.wait();
+    }
+}
@@ -0,0 +1,105 @@
+use serde::Deserialize;
+use std::{
+    error::Error,
+    path::Path,
+};
+
+use crate::output::{
+    FrameDecimator,
+    FrameSink,
+    apng::ApngSink,
+    gif::GifSink,
+    webp::WebpSink,
+};
+
+/// The display time of the last frame when there is no following frame to measure it by.
+pub const DEFAULT_DELAY_MS: f64 = 100.0;
+
+/// Defines the animated image formats the output can be written as.
+#[derive(Clone, Copy, PartialEq)]
+pub enum AnimationFormat {
+    /// An animated GIF, reduced to a palette of at most 256 colors per frame.
+    Gif,
+    /// An animated PNG in full color.
+    Apng,
+    /// A lossless animated WebP in full color.
+    Webp,
+}
+
+impl AnimationFormat {
+    /// Returns the animated format written for the extension of `path`, if it is one.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "gif" => Some(AnimationFormat::Gif),
+            "apng" => Some(AnimationFormat::Apng),
+            "webp" => Some(AnimationFormat::Webp),
+            _ => None,
+        }
+    }
+}
+
+/// Configuration for the animated GIF, APNG and WebP outputs.
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AnimationSettings {
+    /// The number of colors in the palette of each GIF frame, 2 to 256.
+    pub colors: u16,
+    /// If `true`, GIF frames are dithered to hide the banding of the reduced palette.
+    pub dither: bool,
+    /// How many times the animation plays, 0 to loop forever.
+    pub loop_count: u32,
+    /// The highest frame rate of the animation, frames coming in faster are dropped.
+    pub frame_rate: Option<f32>,
+}
+
+impl Default for AnimationSettings {
+    fn default() -> Self {
+        AnimationSettings {
+            colors: 256,
+            dither: true,
+            loop_count: 0,
+            frame_rate: None,
+        }
+    }
+}
+
+impl AnimationSettings {
+    /// Creates the sink writing an animation in `format` to `path`, keeping the alpha channel of
+    /// the frames if `has_alpha` is `true`.
+    pub fn create_sink(&self, path: &Path, format: AnimationFormat, has_alpha: bool) -> Result<Box<dyn FrameSink>, Box<dyn Error>> {
+        let sink: Box<dyn FrameSink> = match format {
+            AnimationFormat::Gif if has_alpha => {
+                return Err("GIFs can't hold the alpha of the trail layer, use an APNG or WebP instead".into());
+            }
+            AnimationFormat::Gif => Box::new(GifSink::new(path, self)?),
+            AnimationFormat::Apng => Box::new(ApngSink::new(path.to_path_buf(), self.loop_count, has_alpha)),
+            AnimationFormat::Webp => Box::new(WebpSink::new(path.to_path_buf(), self.loop_count, has_alpha)),
+        };
+        Ok(match self.frame_rate {
+            Some(frame_rate) => Box::new(FrameDecimator::new(sink, frame_rate)),
+            None => sink,
+        })
+    }
+}
+
+/// Returns how long a frame shown from `start_ms` to `end_ms` lasts in units of `unit_ms`, at
+/// least one. The delay is rounded from both timestamps rather than from the difference, so
+/// rounding errors don't add up over the animation.
+pub fn frame_delay(start_ms: f64, end_ms: f64, unit_ms: f64) -> u32 {
+    let units = |timestamp_ms: f64| (timestamp_ms / unit_ms).round() as i64;
+    (units(end_ms) - units(start_ms)).max(1) as u32
+}
+
+/// Returns the delay of every frame from the timestamps of the frames, see `frame_delay`. The last
+/// frame is shown as long as the one before it.
+pub fn frame_delays(timestamps_ms: &[f64], unit_ms: f64) -> Vec<u32> {
+    let mut delays: Vec<u32> = timestamps_ms
+        .windows(2)
+        .map(|pair| frame_delay(pair[0], pair[1], unit_ms))
+        .collect();
+    if !timestamps_ms.is_empty() {
+        delays.push(delays.last().copied().unwrap_or(frame_delay(0.0, DEFAULT_DELAY_MS, unit_ms)));
+    }
+    delays
+}
@@ -0,0 +1,142 @@
+use image::{
+    DynamicImage,
+    RgbaImage,
+};
+use png::{
+    BitDepth,
+    ColorType,
+    Encoder,
+    Writer,
+};
+use std::{
+    error::Error,
+    fs::{
+        File,
+        OpenOptions,
+    },
+    io::{
+        BufWriter,
+        Read,
+        Seek,
+        SeekFrom,
+        Write,
+    },
+    path::PathBuf,
+};
+use video_rs::Time;
+
+use crate::output::{
+    FrameSink,
+    animation::{
+        DEFAULT_DELAY_MS,
+        frame_delay,
+    },
+};
+
+/// The length of the PNG signature, after which the chunks start.
+const SIGNATURE_LENGTH: u64 = 8;
+
+/// Writes the frames to an animated PNG in full color, with alpha if `has_alpha` is set. Each
+/// frame is written as soon as the next one comes in, which sets how long it is shown. The frame
+/// count at the start of the file is filled in once the last frame is written.
+pub struct ApngSink {
+    path: PathBuf,
+    loop_count: u32,
+    has_alpha: bool,
+    writer: Option<Writer<BufWriter<File>>>,
+    /// The frame waiting for the next one, with its timestamp in milliseconds.
+    pending: Option<(DynamicImage, f64)>,
+    /// The delay of the last written frame, in milliseconds.
+    last_delay_ms: u32,
+    frame_count: u32,
+}
+
+impl ApngSink {
+    /// Creates a sink writing an animated PNG to `path`, created with the first frame.
+    pub fn new(path: PathBuf, loop_count: u32, has_alpha: bool) -> Self {
+        ApngSink {
+            path,
+            loop_count,
+            has_alpha,
+            writer: None,
+            pending: None,
+            last_delay_ms: frame_delay(0.0, DEFAULT_DELAY_MS, 1.0),
+            frame_count: 0,
+        }
+    }
+
+    /// Writes the pending frame, shown for `delay_ms`.
+    fn write_pending(&mut self, delay_ms: u32) -> Result<(), Box<dyn Error>> {
+        let Some((frame, _)) = self.pending.take() else {
+            return Ok(());
+        };
+        let writer = match &mut self.writer {
+            Some(writer) => writer,
+            None => {
+                let mut encoder = Encoder::new(BufWriter::new(File::create(&self.path)?), frame.width(), frame.height());
+                encoder.set_color(if self.has_alpha { ColorType::Rgba } else { ColorType::Rgb });
+                encoder.set_depth(BitDepth::Eight);
+                // The frame count isn't known yet, it is filled in by `finish`
+                encoder.set_animated(u32::MAX, self.loop_count)?;
+                self.writer.insert(encoder.write_header()?)
+            }
+        };
+        writer.set_frame_delay(delay_ms.min(u16::MAX as u32) as u16, 1000)?;
+        writer.write_image_data(frame.as_bytes())?;
+        self.last_delay_ms = delay_ms;
+        self.frame_count += 1;
+        Ok(())
+    }
+}
+
+impl FrameSink for ApngSink {
+    fn write(&mut self, frame: RgbaImage, timestamp: Time) -> Result<(), Box<dyn Error>> {
+        let timestamp_ms = timestamp.as_secs_f64() * 1000.0;
+        if let Some(&(_, pending_ms)) = self.pending.as_ref() {
+            self.write_pending(frame_delay(pending_ms, timestamp_ms, 1.0))?;
+        }
+        let frame = DynamicImage::ImageRgba8(frame);
+        let frame = if self.has_alpha { frame } else { DynamicImage::ImageRgb8(frame.to_rgb8()) };
+        self.pending = Some((frame, timestamp_ms));
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Box<dyn Error>> {
+        // The last frame is shown as long as the one before it
+        self.write_pending(self.last_delay_ms)?;
+        let Some(writer) = self.writer.take() else {
+            return Ok(());
+        };
+        writer.finish()?;
+        set_frame_count(&mut OpenOptions::new().read(true).write(true).open(&self.path)?, self.frame_count)
+    }
+}
+
+/// Overwrites the frame count in the `acTL` chunk of an APNG, and the checksum of the chunk.
+fn set_frame_count(file: &mut File, frame_count: u32) -> Result<(), Box<dyn Error>> {
+    let mut position = SIGNATURE_LENGTH;
+    loop {
+        // Each chunk is its length, type, data and checksum
+        let mut header = [0; 8];
+        file.seek(SeekFrom::Start(position))?;
+        file.read_exact(&mut header)?;
+        let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as u64;
+        match &header[4..] {
+            b"acTL" => break,
+            b"IDAT" | b"IEND" => return Err("The APNG has no acTL chunk".into()),
+            _ => position += 12 + length,
+        }
+    }
+
+    let mut data = [0; 8];
+    file.read_exact(&mut data)?;
+    data[..4].copy_from_slice(&frame_count.to_be_bytes());
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(b"acTL");
+    hasher.update(&data);
+
+    file.seek(SeekFrom::Start(position + 8))?;
+    file.write_all(&data)?;
+    file.write_all(&hasher.finalize().to_be_bytes())?;
+    Ok(())
+}
@@ -0,0 +1,51 @@
+use image::{
+    Rgba,
+    RgbaImage,
+    imageops,
+};
+use std::{
+    error::Error,
+    path::PathBuf,
+};
+use video_rs::Time;
+
+use crate::output::FrameSink;
+
+/// Lays the frames out in a grid and saves it as a single image.
+pub struct ContactSheetSink {
+    path: PathBuf,
+    columns: u32,
+    frames: Vec<RgbaImage>,
+}
+
+impl ContactSheetSink {
+    /// Creates a contact sheet saved to `path`, with `columns` frames per row.
+    pub fn new(path: PathBuf, columns: u32) -> Self {
+        ContactSheetSink { path, columns: columns.max(1), frames: Vec::new() }
+    }
+}
+
+impl FrameSink for ContactSheetSink {
+    fn write(&mut self, frame: RgbaImage, _timestamp: Time) -> Result<(), Box<dyn Error>> {
+        self.frames.push(frame);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Box<dyn Error>> {
+        let Some(first) = self.frames.first() else {
+            return Err("The contact sheet has no frames".into());
+        };
+        let (width, height) = first.dimensions();
+        let count = self.frames.len() as u32;
+        let columns = self.columns.min(count);
+        let rows = count.div_ceil(columns);
+
+        let mut sheet = RgbaImage::from_pixel(columns * width, rows * height, Rgba([0, 0, 0, 255]));
+        for (index, frame) in self.frames.iter().enumerate() {
+            let (column, row) = (index as u32 % columns, index as u32 / columns);
+            imageops::replace(&mut sheet, frame, (column * width) as i64, (row * height) as i64);
+        }
+        sheet.save(&self.path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,164 @@
+use color_quant::NeuQuant;
+use gif::{
+    Encoder,
+    Frame,
+    Repeat,
+};
+use image::RgbaImage;
+use std::{
+    borrow::Cow,
+    error::Error,
+    fs::File,
+    io::BufWriter,
+    path::Path,
+};
+use video_rs::Time;
+
+use crate::output::{
+    FrameSink,
+    animation::{
+        AnimationSettings,
+        DEFAULT_DELAY_MS,
+        frame_delay,
+    },
+};
+
+/// The sampling factor of the palette search, 1 is the most accurate and 30 the fastest.
+const QUANTIZER_SAMPLING: i32 = 10;
+
+/// A frame reduced to a palette, waiting for the next frame to set how long it is shown.
+struct PendingFrame {
+    palette: Vec<u8>,
+    indices: Vec<u8>,
+    timestamp_ms: f64,
+}
+
+/// Writes the frames to an animated GIF. Each frame gets its own palette, so effects with many
+/// colors, such as rainbow trails, keep as many of them as a single frame can hold.
+pub struct GifSink {
+    /// The encoder, created with the size of the first frame.
+    encoder: Option<Encoder<BufWriter<File>>>,
+    file: Option<File>,
+    colors: usize,
+    dither: bool,
+    loop_count: u32,
+    size: (u16, u16),
+    pending: Option<PendingFrame>,
+    /// The delay of the previous frame in centiseconds, reused for the last frame.
+    last_delay: u16,
+}
+
+impl GifSink {
+    /// Creates an animated GIF at `path`.
+    pub fn new(path: &Path, settings: &AnimationSettings) -> Result<Self, Box<dyn Error>> {
+        Ok(GifSink {
+            encoder: None,
+            file: Some(File::create(path)?),
+            colors: settings.colors.clamp(2, 256) as usize,
+            dither: settings.dither,
+            loop_count: settings.loop_count,
+            size: (0, 0),
+            pending: None,
+            last_delay: (DEFAULT_DELAY_MS / 10.0) as u16,
+        })
+    }
+
+    /// Encodes the pending frame, shown for `delay` centiseconds.
+    fn encode(&mut self, pending: PendingFrame, delay: u16) -> Result<(), Box<dyn Error>> {
+        if self.encoder.is_none() {
+            let file = self.file.take().ok_or("The GIF was already written")?;
+            let mut encoder = Encoder::new(BufWriter::new(file), self.size.0, self.size.1, &[])?;
+            // GIFs count the repeats after the first play, without the extension they play once
+            match self.loop_count {
+                0 => encoder.set_repeat(Repeat::Infinite)?,
+                1 => {}
+                plays => encoder.set_repeat(Repeat::Finite((plays - 1).min(u16::MAX as u32) as u16))?,
+            }
+            self.encoder = Some(encoder);
+        }
+        let frame = Frame {
+            width: self.size.0,
+            height: self.size.1,
+            delay,
+            palette: Some(pending.palette),
+            buffer: Cow::Owned(pending.indices),
+            ..Frame::default()
+        };
+        if let Some(encoder) = &mut self.encoder {
+            encoder.write_frame(&frame)?;
+        }
+        Ok(())
+    }
+}
+
+impl FrameSink for GifSink {
+    fn write(&mut self, frame: RgbaImage, timestamp: Time) -> Result<(), Box<dyn Error>> {
+        let timestamp_ms = timestamp.as_secs_f64() * 1000.0;
+        let (width, height) = frame.dimensions();
+        let size = (
+            u16::try_from(width).map_err(|_| "GIF frames can't be wider than 65535 pixels")?,
+            u16::try_from(height).map_err(|_| "GIF frames can't be taller than 65535 pixels")?,
+        );
+        if self.pending.is_none() && self.encoder.is_none() {
+            self.size = size;
+        }
+
+        if let Some(previous) = self.pending.take() {
+            self.last_delay = frame_delay(previous.timestamp_ms, timestamp_ms, 10.0).min(u16::MAX as u32) as u16;
+            self.encode(previous, self.last_delay)?;
+        }
+        let (palette, indices) = quantize(&frame, self.colors, self.dither);
+        self.pending = Some(PendingFrame { palette, indices, timestamp_ms });
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Box<dyn Error>> {
+        if let Some(last) = self.pending.take() {
+            self.encode(last, self.last_delay)?;
+        }
+        // Dropping the encoder writes the trailer of the file
+        self.encoder = None;
+        Ok(())
+    }
+}
+
+/// Reduces a frame to a palette of at most `colors` colors, optionally with Floyd-Steinberg
+/// dithering. Returns the palette as `[r, g, b, ...]` and the palette index of every pixel.
+fn quantize(frame: &RgbaImage, colors: usize, dither: bool) -> (Vec<u8>, Vec<u8>) {
+    let quantizer = NeuQuant::new(QUANTIZER_SAMPLING, colors, frame.as_raw());
+    let palette = quantizer.color_map_rgb();
+    let (width, height) = (frame.width() as usize, frame.height() as usize);
+
+    if !dither {
+        let indices = frame.pixels().map(|pixel| quantizer.index_of(&pixel.0) as u8).collect();
+        return (palette, indices);
+    }
+
+    // The quantization error of each pixel is spread to the following pixels, kept in two rows
+    let mut indices = Vec::with_capacity(width * height);
+    let mut errors = vec![[0.0f32; 3]; width + 2];
+    let mut next_errors = vec![[0.0f32; 3]; width + 2];
+    for y in 0..height {
+        for x in 0..width {
+            let pixel = frame.get_pixel(x as u32, y as u32).0;
+            let mut target = [0u8; 4];
+            for channel in 0..3 {
+                target[channel] = (pixel[channel] as f32 + errors[x + 1][channel]).round().clamp(0.0, 255.0) as u8;
+            }
+            target[3] = 255;
+            let index = quantizer.index_of(&target);
+            indices.push(index as u8);
+
+            for channel in 0..3 {
+                let error = target[channel] as f32 - palette[index * 3 + channel] as f32;
+                errors[x + 2][channel] += error * 7.0 / 16.0;
+                next_errors[x][channel] += error * 3.0 / 16.0;
+                next_errors[x + 1][channel] += error * 5.0 / 16.0;
+                next_errors[x + 2][channel] += error / 16.0;
+            }
+        }
+        std::mem::swap(&mut errors, &mut next_errors);
+        next_errors.iter_mut().for_each(|error| *error = [0.0; 3]);
+    }
+    (palette, indices)
+}
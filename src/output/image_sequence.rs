@@ -0,0 +1,100 @@
+use image::{
+    DynamicImage,
+    ImageFormat,
+    Rgba32FImage,
+    RgbaImage,
+};
+use std::{
+    error::Error,
+    path::Path,
+};
+use video_rs::Time;
+
+use crate::{
+    input::image_sequence::{
+        SequencePattern,
+        srgb_to_linear,
+    },
+    output::FrameSink,
+};
+
+/// Writes every frame to its own numbered image file, in the format given by the extension of
+/// the pattern.
+pub struct ImageSequenceSink {
+    pattern: SequencePattern,
+    format: ImageFormat,
+    /// Whether PNG and TIFF frames are written with 16 bits per channel.
+    is_16_bit: bool,
+    /// Whether the alpha channel of the frames is kept.
+    has_alpha: bool,
+    /// The number of the next frame.
+    number: u64,
+}
+
+impl ImageSequenceSink {
+    /// Creates a sink writing to the files of `pattern`, numbered from `start_number`. If
+    /// `has_alpha` is `true`, the alpha channel of the frames is written too.
+    pub fn new(pattern: SequencePattern, bit_depth: u8, start_number: u64, has_alpha: bool) -> Result<Self, Box<dyn Error>> {
+        let format = image_format(&pattern.path(start_number), bit_depth, has_alpha)?;
+        Ok(ImageSequenceSink { pattern, format, is_16_bit: bit_depth == 16, has_alpha, number: start_number })
+    }
+}
+
+impl FrameSink for ImageSequenceSink {
+    fn write(&mut self, frame: RgbaImage, _timestamp: Time) -> Result<(), Box<dyn Error>> {
+        save_frame(frame, &self.pattern.path(self.number), self.format, self.is_16_bit, self.has_alpha)?;
+        self.number += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+}
+
+/// Returns the format of the image at `path`, checking it can hold `bit_depth` bits per channel
+/// and, if `has_alpha` is `true`, an alpha channel.
+pub fn image_format(path: &Path, bit_depth: u8, has_alpha: bool) -> Result<ImageFormat, Box<dyn Error>> {
+    let format = ImageFormat::from_path(path)?;
+    if bit_depth == 16 && !matches!(format, ImageFormat::Png | ImageFormat::Tiff) {
+        return Err(format!("16-bit output needs PNG or TIFF images, not {}", path.display()).into());
+    }
+    if has_alpha && !matches!(format, ImageFormat::Png | ImageFormat::Tiff | ImageFormat::OpenExr) {
+        return Err(format!("Output with alpha needs PNG, TIFF or EXR images, not {}", path.display()).into());
+    }
+    Ok(format)
+}
+
+/// Saves a frame to `path` in `format`, with 16 bits per channel if `is_16_bit` is `true` and
+/// with its alpha channel if `has_alpha` is `true`. EXR frames are saved as linear float.
+pub fn save_frame(
+    frame: RgbaImage,
+    path: &Path,
+    format: ImageFormat,
+    is_16_bit: bool,
+    has_alpha: bool,
+) -> Result<(), Box<dyn Error>> {
+    // The frames are processed at 8 bits, so 16-bit output widens them without adding precision,
+    // for tools that only take 16-bit or float frames
+    let image = match (format, has_alpha) {
+        (ImageFormat::OpenExr, true) => DynamicImage::ImageRgba32F(premultiply(srgb_to_linear(&frame).to_rgba32f())),
+        (ImageFormat::OpenExr, false) => DynamicImage::ImageRgb32F(srgb_to_linear(&frame).to_rgb32f()),
+        (_, true) if is_16_bit => DynamicImage::ImageRgba16(DynamicImage::ImageRgba8(frame).to_rgba16()),
+        (_, true) => DynamicImage::ImageRgba8(frame),
+        (_, false) if is_16_bit => DynamicImage::ImageRgb16(DynamicImage::ImageRgba8(frame).to_rgb16()),
+        (_, false) => DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(frame).to_rgb8()),
+    };
+    image.save_with_format(path, format)?;
+    Ok(())
+}
+
+/// Multiplies the color of every pixel by its alpha, as EXR files store it.
+fn premultiply(mut image: Rgba32FImage) -> Rgba32FImage {
+    for pixel in image.pixels_mut() {
+        let alpha = pixel[3];
+        for channel in 0..3 {
+            pixel[channel] *= alpha;
+        }
+    }
+    image
+}
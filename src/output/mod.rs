@@ -0,0 +1,303 @@
+use image::RgbaImage;
+use serde::Deserialize;
+use std::{
+    error::Error,
+    path::{
+        Path,
+        PathBuf,
+    },
+};
+use video_rs::{
+    Frame,
+    Time,
+    encode::{
+        Encoder,
+        Settings,
+    },
+};
+
+use crate::{
+    checkpoint::part_path,
+    output::{
+        animation::AnimationSettings,
+        resample::FrameRateConversion,
+    },
+};
+
+pub mod alpha_video;
+pub mod animation;
+pub mod apng;
+pub mod contact_sheet;
+pub mod gif;
+pub mod image_sequence;
+pub mod raw;
+pub mod resample;
+pub mod stills;
+pub mod temp_file;
+pub mod webp;
+
+/// Defines what is written to the output.
+#[allow(dead_code)]
+#[derive(Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputLayer {
+    /// The frames with the trails composited in.
+    Composite,
+    /// Only the trails, with an alpha channel, to composite over other footage in an editor.
+    Trails,
+}
+
+/// Defines what happens if the output already exists.
+#[allow(dead_code)]
+#[derive(Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OverwritePolicy {
+    /// The existing output is replaced once the new one is complete.
+    Overwrite,
+    /// The render fails instead, before any frame is processed.
+    NoClobber,
+}
+
+/// Holds the settings of the rendered video.
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutputSettings {
+    /// Whether the composited frames or only the trail layer are written.
+    pub layer: OutputLayer,
+    /// The video to write, an animated GIF, PNG or WebP, or a numbered image sequence such as
+    /// `out_%05d.png`. Image sequences are written in the format given by the extension (PNG,
+    /// TIFF, EXR, JPEG...).
+    pub path: PathBuf,
+    /// The bits per channel of PNG and TIFF frames, 8 or 16. EXR frames are always 32-bit float.
+    pub bit_depth: u8,
+    /// The number of the first frame of an image sequence.
+    pub start_number: u64,
+    /// The frame rate of the output, none to keep the timing of the input frames.
+    pub frame_rate: Option<f32>,
+    /// How input frames are combined into output frames when `frame_rate` is set.
+    pub frame_rate_conversion: FrameRateConversion,
+    /// The settings of the animated GIF, PNG and WebP outputs.
+    pub animation: AnimationSettings,
+    /// Whether an existing output is replaced.
+    pub overwrite: OverwritePolicy,
+}
+
+impl Default for OutputSettings {
+    fn default() -> Self {
+        OutputSettings {
+            layer: OutputLayer::Composite,
+            path: PathBuf::from("output.mp4"),
+            bit_depth: 8,
+            start_number: 1,
+            frame_rate: None,
+            frame_rate_conversion: FrameRateConversion::Blend,
+            animation: AnimationSettings::default(),
+            overwrite: OverwritePolicy::Overwrite,
+        }
+    }
+}
+
+/// Returns an error unless the video at `path` is named `.mp4`. Videos are encoded by video-rs as
+/// MP4, and keep that container even when FFmpeg adds no audio track to them, so any other
+/// extension would name MP4 bytes.
+pub fn check_video_path(path: &Path) -> Result<(), Box<dyn Error>> {
+    if path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("mp4")) {
+        return Ok(());
+    }
+    Err(format!(
+        "{} can't be written, videos are written as .mp4, or as .mov or .webm with the trail layer",
+        path.display()
+    )
+    .into())
+}
+
+/// A destination for processed frames.
+pub trait FrameSink {
+    /// Writes a processed frame shown at `timestamp`.
+    fn write(&mut self, frame: RgbaImage, timestamp: Time) -> Result<(), Box<dyn Error>>;
+
+    /// Completes the output once every frame is written.
+    fn finish(&mut self) -> Result<(), Box<dyn Error>>;
+
+    /// Completes the frames written so far as a separate part, which is kept if the render
+    /// stops. Only outputs that can be checkpointed support it.
+    fn split(&mut self) -> Result<(), Box<dyn Error>> {
+        Err("This output can't be split into parts for checkpoints".into())
+    }
+}
+
+/// Encodes the frames to an H.264 video file.
+pub struct VideoSink {
+    encoder: Encoder,
+    /// If `true`, the timestamp of the first frame is subtracted from every frame.
+    starts_at_zero: bool,
+    /// The timestamp of the first frame, set by the first frame.
+    start: Option<Time>,
+}
+
+impl VideoSink {
+    /// Creates a video file at `path` for frames of `width` by `height` pixels.
+    pub fn new(path: &Path, width: u32, height: u32) -> Result<Self, Box<dyn Error>> {
+        let encoder = Encoder::new(
+            path,
+            Settings::preset_h264_yuv420p(width as usize, height as usize, false),
+        )?;
+        Ok(VideoSink { encoder, starts_at_zero: false, start: None })
+    }
+
+    /// Creates a video file like `new`, whose timestamps start at zero whatever the timestamp of
+    /// the first frame. An excerpt of the source, such as a preview or a checkpoint part, would
+    /// otherwise get an empty edit up to its first frame, which plays as a gap or frozen frame.
+    pub fn starting_at_zero(path: &Path, width: u32, height: u32) -> Result<Self, Box<dyn Error>> {
+        Ok(VideoSink { starts_at_zero: true, ..VideoSink::new(path, width, height)? })
+    }
+}
+
+impl FrameSink for VideoSink {
+    fn write(&mut self, frame: RgbaImage, timestamp: Time) -> Result<(), Box<dyn Error>> {
+        let timestamp = if self.starts_at_zero {
+            let start = *self.start.get_or_insert(timestamp);
+            timestamp.aligned_with(start).subtract()
+        } else {
+            timestamp
+        };
+        let (width, height) = frame.dimensions();
+        let rgb_output = image::DynamicImage::ImageRgba8(frame).to_rgb8();
+        let frame_to_encode: Frame =
+            Frame::from_shape_vec((height as usize, width as usize, 3), rgb_output.into_raw())
+                .expect("Could not create ndarray from image buffer");
+        self.encoder.encode(&frame_to_encode, timestamp)?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Box<dyn Error>> {
+        self.encoder.finish()?;
+        Ok(())
+    }
+}
+
+/// Encodes the frames to H.264 videos in a checkpoint directory, starting a new part at every
+/// checkpoint. The parts are joined once the render is complete. Every part starts at timestamp
+/// zero, as the concat demuxer joining them expects.
+pub struct SplitVideoSink {
+    dir: PathBuf,
+    width: u32,
+    height: u32,
+    /// The part being written, created with the first frame after a split.
+    part: Option<VideoSink>,
+    /// The index of the next part to create.
+    next_index: usize,
+}
+
+impl SplitVideoSink {
+    /// Creates a sink writing parts of frames of `width` by `height` pixels to `dir`, starting
+    /// with the part numbered `first_index`.
+    pub fn new(dir: &Path, first_index: usize, width: u32, height: u32) -> Self {
+        SplitVideoSink { dir: dir.to_path_buf(), width, height, part: None, next_index: first_index }
+    }
+}
+
+impl FrameSink for SplitVideoSink {
+    fn write(&mut self, frame: RgbaImage, timestamp: Time) -> Result<(), Box<dyn Error>> {
+        let part = match &mut self.part {
+            Some(part) => part,
+            None => self.part.insert(VideoSink::starting_at_zero(
+                &part_path(&self.dir, self.next_index),
+                self.width,
+                self.height,
+            )?),
+        };
+        part.write(frame, timestamp)
+    }
+
+    fn finish(&mut self) -> Result<(), Box<dyn Error>> {
+        self.split()
+    }
+
+    fn split(&mut self) -> Result<(), Box<dyn Error>> {
+        if let Some(mut part) = self.part.take() {
+            part.finish()?;
+            self.next_index += 1;
+        }
+        Ok(())
+    }
+}
+
+/// Passes only every `step`-th frame on to another sink.
+pub struct FrameStep {
+    sink: Box<dyn FrameSink>,
+    step: usize,
+    count: usize,
+}
+
+impl FrameStep {
+    /// Creates a sink writing every `step`-th frame to `sink`, starting with the first.
+    pub fn new(sink: Box<dyn FrameSink>, step: usize) -> Self {
+        FrameStep { sink, step: step.max(1), count: 0 }
+    }
+}
+
+impl FrameSink for FrameStep {
+    fn write(&mut self, frame: RgbaImage, timestamp: Time) -> Result<(), Box<dyn Error>> {
+        let is_written = self.count.is_multiple_of(self.step);
+        self.count += 1;
+        if is_written {
+            self.sink.write(frame, timestamp)?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Box<dyn Error>> {
+        self.sink.finish()
+    }
+}
+
+/// Drops frames coming in faster than a given frame rate, passing the rest on to another sink.
+pub struct FrameDecimator {
+    sink: Box<dyn FrameSink>,
+    interval_ms: f64,
+    /// The timestamp of the first frame, in milliseconds.
+    start_ms: Option<f64>,
+    /// The index of the next interval a frame is passed on in.
+    next_index: u64,
+}
+
+impl FrameDecimator {
+    /// Creates a sink writing at most `frame_rate` frames per second to `sink`.
+    pub fn new(sink: Box<dyn FrameSink>, frame_rate: f32) -> Self {
+        FrameDecimator { sink, interval_ms: 1000.0 / frame_rate as f64, start_ms: None, next_index: 0 }
+    }
+}
+
+impl FrameSink for FrameDecimator {
+    fn write(&mut self, frame: RgbaImage, timestamp: Time) -> Result<(), Box<dyn Error>> {
+        let timestamp_ms = timestamp.as_secs_f64() * 1000.0;
+        let start_ms = *self.start_ms.get_or_insert(timestamp_ms);
+
+        // A frame is kept if it's the first one within its interval
+        let index = ((timestamp_ms - start_ms + 1e-3) / self.interval_ms).floor() as u64;
+        if index >= self.next_index {
+            self.next_index = index + 1;
+            self.sink.write(frame, timestamp)?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Box<dyn Error>> {
+        self.sink.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn video_path_needs_the_mp4_extension() {
+        assert!(check_video_path(Path::new("renders/clip.mp4")).is_ok());
+        assert!(check_video_path(Path::new("renders/clip.MP4")).is_ok());
+        assert!(check_video_path(Path::new("renders/clip.mkv")).is_err());
+        assert!(check_video_path(Path::new("renders/clip.mov")).is_err());
+        assert!(check_video_path(Path::new("renders/clip")).is_err());
+    }
+}
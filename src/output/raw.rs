@@ -0,0 +1,70 @@
+use image::RgbaImage;
+use std::{
+    error::Error,
+    io::{
+        self,
+        BufWriter,
+        Write,
+    },
+    process::{
+        Child,
+        Command,
+        Stdio,
+    },
+};
+use video_rs::Time;
+
+use crate::{
+    input::raw::RawPixelFormat,
+    output::FrameSink,
+};
+
+/// Writes the frames as raw video to stdout or to the stdin of a player. Every frame is flushed
+/// as soon as it is written, to keep the latency low.
+pub struct RawPipeSink {
+    writer: Option<Box<dyn Write>>,
+    format: RawPixelFormat,
+    /// The player the frames are piped to, if they aren't written to stdout.
+    player: Option<Child>,
+}
+
+impl RawPipeSink {
+    /// Creates a sink writing raw frames in `format` to stdout.
+    pub fn stdout(format: RawPixelFormat) -> Self {
+        RawPipeSink { writer: Some(Box::new(BufWriter::new(io::stdout()))), format, player: None }
+    }
+
+    /// Opens an FFplay window showing frames of `width` by `height` pixels, as they come.
+    pub fn window(width: u32, height: u32, frame_rate: f32) -> Result<Self, Box<dyn Error>> {
+        let format = RawPixelFormat::Rgb24;
+        let mut player = Command::new("ffplay")
+            .args(["-loglevel", "error", "-fflags", "nobuffer", "-window_title", "sticky_frame"])
+            .args(["-f", "rawvideo", "-pixel_format", format.ffmpeg_name()])
+            .args(["-video_size", &format!("{}x{}", width, height), "-framerate", &frame_rate.to_string(), "-i", "-"])
+            .stdin(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Failed to start FFplay, which shows the live window: {}", e))?;
+        let stdin = player.stdin.take().ok_or("Failed to open the stdin of FFplay")?;
+        Ok(RawPipeSink { writer: Some(Box::new(stdin)), format, player: Some(player) })
+    }
+}
+
+impl FrameSink for RawPipeSink {
+    fn write(&mut self, frame: RgbaImage, _timestamp: Time) -> Result<(), Box<dyn Error>> {
+        let writer = self.writer.as_mut().ok_or("The raw video was already finished")?;
+        writer.write_all(&self.format.to_bytes(frame))?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Box<dyn Error>> {
+        if let Some(mut writer) = self.writer.take() {
+            writer.flush()?;
+        }
+        // The player keeps showing the last frame until its window is closed
+        if let Some(player) = self.player.as_mut() {
+            player.wait()?;
+        }
+        Ok(())
+    }
+}
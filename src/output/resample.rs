@@ -0,0 +1,127 @@
+use image::RgbaImage;
+use serde::Deserialize;
+use std::error::Error;
+use video_rs::Time;
+
+use crate::output::FrameSink;
+
+/// Defines how frames are combined when the output frame rate differs from the input.
+#[allow(dead_code)]
+#[derive(Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FrameRateConversion {
+    /// Averages every input frame within an output frame's interval, like a camera shutter
+    /// that stays open for the whole frame.
+    Blend,
+    /// Takes one input frame per output frame, dropping or duplicating frames as needed.
+    Nearest,
+}
+
+/// Converts a stream of processed frames to a constant output frame rate before passing them on
+/// to another sink.
+/// Output frame `n` covers the interval starting `n / frame_rate` seconds after the first frame.
+pub struct FrameResampler {
+    sink: Box<dyn FrameSink>,
+    conversion: FrameRateConversion,
+    interval_ms: f64,
+    /// The timestamp of the first frame, in milliseconds.
+    start_ms: Option<f64>,
+    /// The index of the output frame currently being filled.
+    output_index: u64,
+    /// The sum of the frames within the current interval, for `FrameRateConversion::Blend`.
+    sum: Vec<f32>,
+    /// The first frame within the current interval, for `FrameRateConversion::Nearest`.
+    first: Option<RgbaImage>,
+    /// The number of frames within the current interval.
+    count: u32,
+    /// The most recent frame, repeated for intervals without any frame.
+    last: Option<RgbaImage>,
+}
+
+impl FrameResampler {
+    /// Creates a resampler writing to `sink` at the given output `frame_rate`.
+    pub fn new(sink: Box<dyn FrameSink>, frame_rate: f32, conversion: FrameRateConversion) -> Self {
+        FrameResampler {
+            sink,
+            conversion,
+            interval_ms: 1000.0 / frame_rate as f64,
+            start_ms: None,
+            output_index: 0,
+            sum: Vec::new(),
+            first: None,
+            count: 0,
+            last: None,
+        }
+    }
+
+    /// Builds the output frame of the current interval and resets it. Intervals without any
+    /// frame repeat the most recent one.
+    fn take_interval(&mut self) -> Option<RgbaImage> {
+        let count = std::mem::take(&mut self.count);
+        if count == 0 {
+            return self.last.clone();
+        }
+        match self.conversion {
+            FrameRateConversion::Blend => {
+                let (width, height) = self.last.as_ref()?.dimensions();
+                let pixels = self.sum.iter().map(|sum| (sum / count as f32).round() as u8).collect();
+                RgbaImage::from_raw(width, height, pixels)
+            }
+            FrameRateConversion::Nearest => self.first.take(),
+        }
+    }
+
+    /// Returns the timestamp of the current output frame.
+    fn output_timestamp(&self) -> Time {
+        let start_ms = self.start_ms.unwrap_or(0.0);
+        Time::from_secs_f64((start_ms + self.output_index as f64 * self.interval_ms) / 1000.0)
+    }
+}
+
+impl FrameSink for FrameResampler {
+    fn write(&mut self, frame: RgbaImage, timestamp: Time) -> Result<(), Box<dyn Error>> {
+        let timestamp_ms = timestamp.as_secs_f64() * 1000.0;
+        let start_ms = *self.start_ms.get_or_insert(timestamp_ms);
+
+        // Every interval ending at or before this frame is complete. The tolerance keeps frames on
+        // an interval boundary from landing in the wrong interval through rounding.
+        while timestamp_ms + 1e-3 >= start_ms + (self.output_index + 1) as f64 * self.interval_ms {
+            if let Some(output_frame) = self.take_interval() {
+                let output_timestamp = self.output_timestamp();
+                self.sink.write(output_frame, output_timestamp)?;
+            }
+            self.output_index += 1;
+        }
+
+        match self.conversion {
+            FrameRateConversion::Blend => {
+                if self.count == 0 {
+                    self.sum.clear();
+                    self.sum.resize(frame.as_raw().len(), 0.0);
+                }
+                for (sum, value) in self.sum.iter_mut().zip(frame.as_raw()) {
+                    *sum += *value as f32;
+                }
+            }
+            FrameRateConversion::Nearest => {
+                if self.count == 0 {
+                    self.first = Some(frame.clone());
+                }
+            }
+        }
+        self.count += 1;
+        self.last = Some(frame);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Box<dyn Error>> {
+        // Write the last, partially filled interval
+        if self.count > 0
+            && let Some(output_frame) = self.take_interval()
+        {
+            let output_timestamp = self.output_timestamp();
+            self.sink.write(output_frame, output_timestamp)?;
+        }
+        self.sink.finish()
+    }
+}
@@ -0,0 +1,237 @@
+use image::{
+    ImageFormat,
+    RgbaImage,
+};
+use log::{
+    info,
+    warn,
+};
+use serde::Deserialize;
+use std::{
+    error::Error,
+    path::{
+        Path,
+        PathBuf,
+    },
+};
+use video_rs::Time;
+
+use crate::output::{
+    FrameSink,
+    image_sequence::{
+        image_format,
+        save_frame,
+    },
+};
+
+/// Defines what a still is taken of.
+#[allow(dead_code)]
+#[derive(Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StillSource {
+    /// The accumulated canvas of the effect, without the live video. With an effect stack, the
+    /// canvas of the last stage.
+    Canvas,
+    /// The rendered frame, as written to the video.
+    Frame,
+}
+
+/// Configuration for the still images of the effect canvas or the rendered frames, such as the
+/// accumulated canvas of a light painting at the end of the video.
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StillSettings {
+    /// The image the last still is saved to. Stills at other times are saved next to it, with
+    /// their timestamp in milliseconds added to the file name, such as `still_012000.png`.
+    pub path: PathBuf,
+    /// If `true`, a still of the last frame is saved.
+    pub at_end: bool,
+    /// The timestamps to save a still at, in milliseconds.
+    pub timestamps_ms: Vec<f64>,
+    /// The time between two stills, in milliseconds, none for no regular stills.
+    pub interval_ms: Option<f64>,
+    /// If `true`, only the stills are written, without encoding a video.
+    pub only: bool,
+    /// What the stills are taken of.
+    pub source: StillSource,
+}
+
+impl Default for StillSettings {
+    fn default() -> Self {
+        StillSettings {
+            path: PathBuf::from("still.png"),
+            at_end: false,
+            timestamps_ms: Vec::new(),
+            interval_ms: None,
+            only: false,
+            source: StillSource::Canvas,
+        }
+    }
+}
+
+impl StillSettings {
+    /// Returns `true` if any still is requested.
+    pub fn is_enabled(&self) -> bool {
+        self.at_end || !self.timestamps_ms.is_empty() || self.interval_ms.is_some()
+    }
+
+    /// Returns the path of the still shown at `timestamp_ms`.
+    fn timed_path(&self, timestamp_ms: f64) -> PathBuf {
+        let stem = self.path.file_stem().and_then(|stem| stem.to_str()).unwrap_or("still");
+        let mut file_name = format!("{}_{:06}", stem, timestamp_ms.round() as u64);
+        if let Some(extension) = self.path.extension().and_then(|extension| extension.to_str()) {
+            file_name = format!("{}.{}", file_name, extension);
+        }
+        self.path.with_file_name(file_name)
+    }
+}
+
+/// Saves the frames shown at the requested times as still images, passing every frame on to
+/// another sink. Canvas stills are saved from the loop with `save_before`, `save_at` and
+/// `save_end` instead, only converting the canvas when a still is due, with a `NullSink` after
+/// it.
+pub struct StillSink {
+    sink: Box<dyn FrameSink>,
+    settings: StillSettings,
+    format: ImageFormat,
+    is_16_bit: bool,
+    has_alpha: bool,
+    /// The requested timestamps not reached yet, latest first.
+    pending_ms: Vec<f64>,
+    /// The timestamp of the next regular still, set by the first frame.
+    next_interval_ms: Option<f64>,
+    /// The most recent frame written to the sink.
+    last: Option<RgbaImage>,
+}
+
+impl StillSink {
+    /// Creates a sink saving stills with `bit_depth` bits per channel, and an alpha channel if
+    /// `has_alpha` is `true`, before writing the frames to `sink`.
+    pub fn new(sink: Box<dyn FrameSink>, settings: &StillSettings, bit_depth: u8, has_alpha: bool) -> Result<Self, Box<dyn Error>> {
+        let format = image_format(&settings.path, bit_depth, has_alpha)?;
+        let mut pending_ms = settings.timestamps_ms.clone();
+        pending_ms.sort_by(|a, b| b.total_cmp(a));
+        Ok(StillSink {
+            sink,
+            settings: settings.clone(),
+            format,
+            is_16_bit: bit_depth == 16,
+            has_alpha,
+            pending_ms,
+            next_interval_ms: None,
+            last: None,
+        })
+    }
+
+    /// Returns `true` if a still is due before `timestamp_ms`, which shows the frame before the
+    /// one at `timestamp_ms`.
+    pub fn is_due_before(&self, timestamp_ms: f64) -> bool {
+        self.next_due_ms().is_some_and(|due_ms| due_ms <= timestamp_ms - 1e-3)
+    }
+
+    /// Returns `true` if a still is due at or before `timestamp_ms`. The first frame is always
+    /// due if regular stills are requested.
+    pub fn is_due(&self, timestamp_ms: f64) -> bool {
+        (self.settings.interval_ms.is_some() && self.next_interval_ms.is_none())
+            || self.next_due_ms().is_some_and(|due_ms| due_ms <= timestamp_ms + 1e-3)
+    }
+
+    /// Saves the stills due before `timestamp_ms` of `frame`, the frame shown until then.
+    pub fn save_before(&mut self, frame: &RgbaImage, timestamp_ms: f64) -> Result<(), Box<dyn Error>> {
+        self.save_due(frame, timestamp_ms - 1e-3)
+    }
+
+    /// Saves the stills due at or before `timestamp_ms` of `frame`, the frame shown at
+    /// `timestamp_ms`. The first frame starts the regular stills.
+    pub fn save_at(&mut self, frame: &RgbaImage, timestamp_ms: f64) -> Result<(), Box<dyn Error>> {
+        if self.settings.interval_ms.is_some() && self.next_interval_ms.is_none() {
+            self.next_interval_ms = Some(timestamp_ms);
+        }
+        self.save_due(frame, timestamp_ms + 1e-3)
+    }
+
+    /// Saves the still at the end of `last`, the last frame, if one is requested, and warns about
+    /// the requested stills after the end.
+    pub fn save_end(&self, last: Option<&RgbaImage>) -> Result<(), Box<dyn Error>> {
+        if let Some(&target_ms) = self.pending_ms.last() {
+            warn!("No still was saved at {} ms or later, after the end of the video", target_ms);
+        }
+        if self.settings.at_end
+            && let Some(last) = last
+        {
+            self.save(last, &self.settings.path)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the timestamp of the next requested or regular still, none if there is none left
+    /// or the regular stills haven't started.
+    fn next_due_ms(&self) -> Option<f64> {
+        match (self.pending_ms.last().copied(), self.next_interval_ms) {
+            (Some(pending_ms), Some(interval_ms)) => Some(pending_ms.min(interval_ms)),
+            (pending_ms, interval_ms) => pending_ms.or(interval_ms),
+        }
+    }
+
+    /// Saves every still due at or before `limit_ms` of `frame`.
+    fn save_due(&mut self, frame: &RgbaImage, limit_ms: f64) -> Result<(), Box<dyn Error>> {
+        while let Some(&target_ms) = self.pending_ms.last() {
+            if target_ms > limit_ms {
+                break;
+            }
+            self.pending_ms.pop();
+            self.save(frame, &self.settings.timed_path(target_ms))?;
+        }
+
+        if let (Some(interval_ms), Some(mut next_ms)) = (self.settings.interval_ms, self.next_interval_ms) {
+            while next_ms <= limit_ms {
+                self.save(frame, &self.settings.timed_path(next_ms))?;
+                next_ms += interval_ms;
+            }
+            self.next_interval_ms = Some(next_ms);
+        }
+        Ok(())
+    }
+
+    /// Saves a still to `path`.
+    fn save(&self, frame: &RgbaImage, path: &Path) -> Result<(), Box<dyn Error>> {
+        save_frame(frame.clone(), path, self.format, self.is_16_bit, self.has_alpha)?;
+        info!("Saved a still to {}", path.display());
+        Ok(())
+    }
+}
+
+impl FrameSink for StillSink {
+    fn write(&mut self, frame: RgbaImage, timestamp: Time) -> Result<(), Box<dyn Error>> {
+        let timestamp_ms = timestamp.as_secs_f64() * 1000.0;
+        // Stills between the last frame and this one show the last frame
+        if let Some(last) = self.last.take() {
+            self.save_before(&last, timestamp_ms)?;
+        }
+        self.save_at(&frame, timestamp_ms)?;
+
+        // The frame is only kept while a still may still need it
+        if self.settings.at_end || self.next_due_ms().is_some() {
+            self.last = Some(frame.clone());
+        }
+        self.sink.write(frame, timestamp)
+    }
+
+    fn finish(&mut self) -> Result<(), Box<dyn Error>> {
+        self.save_end(self.last.as_ref())?;
+        self.sink.finish()
+    }
+}
+
+/// Discards every frame, for renders that only save stills.
+pub struct NullSink;
+
+impl FrameSink for NullSink {
+    fn write(&mut self, _frame: RgbaImage, _timestamp: Time) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+}
@@ -0,0 +1,76 @@
+use std::{
+    error::Error,
+    fs,
+    io::ErrorKind,
+    path::{
+        Path,
+        PathBuf,
+    },
+    process,
+    sync::atomic::{
+        AtomicU64,
+        Ordering,
+    },
+};
+
+use crate::output::OverwritePolicy;
+
+/// Numbers the temporary files of this process, which may render several videos at once.
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// A temporary file next to an output, moved into place once it is complete. The file is removed
+/// if it is dropped before that, such as when rendering fails or is interrupted, so no partial
+/// output is left behind.
+pub struct TempFile {
+    path: PathBuf,
+    destination: PathBuf,
+}
+
+impl TempFile {
+    /// Picks a temporary path with `extension` next to `destination`, unique among the processes
+    /// and renders running at once. The file itself isn't created.
+    pub fn next_to(destination: &Path, extension: &str) -> Self {
+        let stem = destination.file_stem().map(|stem| stem.to_string_lossy()).unwrap_or_default();
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let file_name = format!(".{}.{}-{}.tmp.{}", stem, process::id(), id, extension);
+        TempFile { path: destination.with_file_name(file_name), destination: destination.to_path_buf() }
+    }
+
+    /// Returns the temporary path.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Moves the file to its destination in a single step, so the destination is either the
+    /// previous file or the complete new one. With `OverwritePolicy::NoClobber`, an existing
+    /// destination is an error, even one created during the render.
+    pub fn persist(self, policy: OverwritePolicy) -> Result<(), Box<dyn Error>> {
+        match policy {
+            OverwritePolicy::Overwrite => fs::rename(&self.path, &self.destination)?,
+            // Unlike a rename, a hard link fails if the destination exists
+            OverwritePolicy::NoClobber => match fs::hard_link(&self.path, &self.destination) {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                    return Err(format!("{} already exists, and the overwrite policy is no_clobber", self.destination.display()).into());
+                }
+                // Some file systems have no hard links, where checking first is the best there is
+                Err(_) => {
+                    if self.destination.exists() {
+                        return Err(format!("{} already exists, and the overwrite policy is no_clobber", self.destination.display()).into());
+                    }
+                    fs::rename(&self.path, &self.destination)?;
+                }
+            },
+        }
+        Ok(())
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        // Also removes the link left behind by a no-clobber persist
+        if self.path.exists() {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
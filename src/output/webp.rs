@@ -0,0 +1,128 @@
+use image::{
+    ExtendedColorType,
+    RgbaImage,
+    codecs::webp::WebPEncoder,
+};
+use std::{
+    error::Error,
+    fs,
+    path::PathBuf,
+};
+use video_rs::Time;
+
+use crate::output::{
+    FrameSink,
+    animation::frame_delays,
+};
+
+/// The largest value of the 24-bit fields of the WebP container.
+const MAX_24_BIT: u32 = (1 << 24) - 1;
+
+/// Writes the frames to a lossless animated WebP, with alpha if `has_alpha` is set. Each frame is
+/// compressed as it comes in, and the file is put together once the last one is in, as its header
+/// holds the total size.
+pub struct WebpSink {
+    path: PathBuf,
+    loop_count: u32,
+    has_alpha: bool,
+    size: (u32, u32),
+    /// The `VP8L` chunk of every frame, with its header.
+    chunks: Vec<Vec<u8>>,
+    timestamps_ms: Vec<f64>,
+}
+
+impl WebpSink {
+    /// Creates a sink writing an animated WebP to `path` once every frame is written.
+    pub fn new(path: PathBuf, loop_count: u32, has_alpha: bool) -> Self {
+        WebpSink { path, loop_count, has_alpha, size: (0, 0), chunks: Vec::new(), timestamps_ms: Vec::new() }
+    }
+}
+
+impl FrameSink for WebpSink {
+    fn write(&mut self, frame: RgbaImage, timestamp: Time) -> Result<(), Box<dyn Error>> {
+        let (width, height) = frame.dimensions();
+        let mut encoded = Vec::new();
+        let encoder = WebPEncoder::new_lossless(&mut encoded);
+        if self.has_alpha {
+            encoder.encode(frame.as_raw(), width, height, ExtendedColorType::Rgba8)?;
+        } else {
+            let rgb_frame = image::DynamicImage::ImageRgba8(frame).to_rgb8();
+            encoder.encode(rgb_frame.as_raw(), width, height, ExtendedColorType::Rgb8)?;
+        }
+
+        self.size = (width, height);
+        self.chunks.push(find_chunk(&encoded, b"VP8L").ok_or("The WebP encoder wrote no VP8L chunk")?.to_vec());
+        self.timestamps_ms.push(timestamp.as_secs_f64() * 1000.0);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.chunks.is_empty() {
+            return Ok(());
+        }
+        let (width, height) = self.size;
+
+        // The extended header with the animation and alpha flags, and the canvas size
+        let mut vp8x = vec![if self.has_alpha { 0x12 } else { 0x02 }, 0, 0, 0];
+        vp8x.extend_from_slice(&u24(width - 1));
+        vp8x.extend_from_slice(&u24(height - 1));
+
+        // A black or transparent background, and how many times the animation plays
+        let mut anim = vec![0, 0, 0, if self.has_alpha { 0 } else { 255 }];
+        anim.extend_from_slice(&(self.loop_count.min(u16::MAX as u32) as u16).to_le_bytes());
+
+        let mut body = b"WEBP".to_vec();
+        push_chunk(&mut body, b"VP8X", &vp8x);
+        push_chunk(&mut body, b"ANIM", &anim);
+
+        let delays = frame_delays(&self.timestamps_ms, 1.0);
+        for (chunk, delay) in self.chunks.drain(..).zip(delays) {
+            // Every frame covers the whole canvas at offset (0, 0) and replaces the one before it
+            let mut anmf = Vec::with_capacity(16 + chunk.len());
+            anmf.extend_from_slice(&u24(0));
+            anmf.extend_from_slice(&u24(0));
+            anmf.extend_from_slice(&u24(width - 1));
+            anmf.extend_from_slice(&u24(height - 1));
+            anmf.extend_from_slice(&u24(delay.min(MAX_24_BIT)));
+            anmf.push(0x02);
+            anmf.extend_from_slice(&chunk);
+            push_chunk(&mut body, b"ANMF", &anmf);
+        }
+
+        let mut file = b"RIFF".to_vec();
+        file.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        file.extend_from_slice(&body);
+        fs::write(&self.path, file)?;
+        Ok(())
+    }
+}
+
+/// Returns the chunk with the id `fourcc` in a WebP file, including its header and padding.
+fn find_chunk<'a>(file: &'a [u8], fourcc: &[u8; 4]) -> Option<&'a [u8]> {
+    let mut position = 12;
+    while position + 8 <= file.len() {
+        let size = u32::from_le_bytes(file[position + 4..position + 8].try_into().ok()?) as usize;
+        let end = (position + 8 + size + size % 2).min(file.len());
+        if &file[position..position + 4] == fourcc {
+            return Some(&file[position..end]);
+        }
+        position = end;
+    }
+    None
+}
+
+/// Appends a chunk with the id `fourcc`, padded to an even size.
+fn push_chunk(file: &mut Vec<u8>, fourcc: &[u8; 4], data: &[u8]) {
+    file.extend_from_slice(fourcc);
+    file.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    file.extend_from_slice(data);
+    if data.len() % 2 == 1 {
+        file.push(0);
+    }
+}
+
+/// Encodes the 24-bit little-endian fields of the WebP container.
+fn u24(value: u32) -> [u8; 3] {
+    let bytes = value.to_le_bytes();
+    [bytes[0], bytes[1], bytes[2]]
+}
@@ -0,0 +1,234 @@
+use image::{
+    RgbaImage,
+    imageops::{
+        self,
+        FilterType,
+    },
+};
+use serde::Deserialize;
+use std::{
+    error::Error,
+    path::Path,
+};
+use video_rs::ffmpeg;
+
+/// Defines the filters available for scaling frames.
+#[allow(dead_code)]
+#[derive(Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScaleFilter {
+    /// Picks the nearest pixel. Fastest, but blocky.
+    Nearest,
+    /// Interpolates linearly between pixels.
+    Triangle,
+    /// A sharp cubic filter.
+    CatmullRom,
+    /// A soft filter.
+    Gaussian,
+    /// The sharpest filter, and the slowest.
+    Lanczos3,
+}
+
+impl From<ScaleFilter> for FilterType {
+    fn from(filter: ScaleFilter) -> Self {
+        match filter {
+            ScaleFilter::Nearest => FilterType::Nearest,
+            ScaleFilter::Triangle => FilterType::Triangle,
+            ScaleFilter::CatmullRom => FilterType::CatmullRom,
+            ScaleFilter::Gaussian => FilterType::Gaussian,
+            ScaleFilter::Lanczos3 => FilterType::Lanczos3,
+        }
+    }
+}
+
+/// Defines the region of the frame kept by the crop.
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Crop {
+    /// A rectangle in pixels of the rotated frame, from its top-left corner.
+    Rect { x: u32, y: u32, width: u32, height: u32 },
+    /// The largest centered region with the aspect ratio `[width, height]`, for example `[9, 16]`.
+    Aspect([u32; 2]),
+}
+
+/// Configuration for the preprocessing applied to every frame before the effect.
+/// Frames are rotated first, then cropped, then scaled.
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PreprocessSettings {
+    /// The clockwise rotation in degrees (0, 90, 180 or 270), none to follow the rotation
+    /// metadata of the video.
+    pub rotation: Option<u32>,
+    /// The region of the rotated frame to keep, none to keep the whole frame.
+    pub crop: Option<Crop>,
+    /// The width to scale to. If only one of `width` and `height` is set, the other follows the
+    /// aspect ratio of the cropped frame.
+    pub width: Option<u32>,
+    /// The height to scale to.
+    pub height: Option<u32>,
+    /// The filter used for scaling.
+    pub filter: ScaleFilter,
+}
+
+impl Default for PreprocessSettings {
+    fn default() -> Self {
+        PreprocessSettings {
+            rotation: None,
+            crop: None,
+            width: None,
+            height: None,
+            filter: ScaleFilter::CatmullRom,
+        }
+    }
+}
+
+/// The preprocessing resolved for the frames of one video.
+pub struct Preprocessor {
+    /// The clockwise rotation in degrees.
+    rotation: u32,
+    /// The crop rectangle `(x, y, width, height)` in the rotated frame.
+    crop: Option<(u32, u32, u32, u32)>,
+    /// The size the cropped frame is scaled to.
+    size: Option<(u32, u32)>,
+    filter: FilterType,
+    output_size: (u32, u32),
+}
+
+impl Preprocessor {
+    /// Resolves the `settings` for decoded frames of `width` by `height` pixels, where `rotation`
+    /// is the clockwise rotation to apply.
+    pub fn new(settings: &PreprocessSettings, (width, height): (u32, u32), rotation: u32) -> Result<Self, Box<dyn Error>> {
+        if !rotation.is_multiple_of(90) {
+            return Err(format!("preprocess: the rotation must be a multiple of 90 degrees, got {}", rotation).into());
+        }
+        let rotation = rotation % 360;
+        let (width, height) = if rotation % 180 == 90 { (height, width) } else { (width, height) };
+
+        // The encoder needs even dimensions, so the crop and scale sizes are rounded down to even
+        let crop = match settings.crop {
+            None => None,
+            Some(Crop::Rect { x, y, width: crop_width, height: crop_height }) => {
+                if x.checked_add(crop_width).is_none_or(|right| right > width)
+                    || y.checked_add(crop_height).is_none_or(|bottom| bottom > height)
+                {
+                    return Err(format!("preprocess: the crop rectangle doesn't fit in the {}x{} frame", width, height).into());
+                }
+                Some((x, y, even(crop_width), even(crop_height)))
+            }
+            Some(Crop::Aspect([aspect_width, aspect_height])) => {
+                if aspect_width == 0 || aspect_height == 0 {
+                    return Err("preprocess: the crop aspect ratio can't be zero".into());
+                }
+                let scale = (width as f64 / aspect_width as f64).min(height as f64 / aspect_height as f64);
+                let crop_width = even((aspect_width as f64 * scale) as u32);
+                let crop_height = even((aspect_height as f64 * scale) as u32);
+                Some(((width - crop_width) / 2, (height - crop_height) / 2, crop_width, crop_height))
+            }
+        };
+        if crop.is_some_and(|(_, _, width, height)| width == 0 || height == 0) {
+            return Err("preprocess: the cropped frame is too small".into());
+        }
+        let (width, height) = crop.map_or((width, height), |(_, _, width, height)| (width, height));
+
+        let aspect = width as f64 / height as f64;
+        let size = match (settings.width, settings.height) {
+            (None, None) => None,
+            (Some(target_width), Some(target_height)) => Some((target_width, target_height)),
+            (Some(target_width), None) => Some((target_width, (target_width as f64 / aspect).round() as u32)),
+            (None, Some(target_height)) => Some(((target_height as f64 * aspect).round() as u32, target_height)),
+        }
+        .map(|(width, height)| (even(width), even(height)));
+        if size.is_some_and(|(width, height)| width == 0 || height == 0) {
+            return Err("preprocess: the scaled size is too small".into());
+        }
+
+        Ok(Preprocessor {
+            rotation,
+            crop,
+            size,
+            filter: settings.filter.into(),
+            output_size: size.unwrap_or((width, height)),
+        })
+    }
+
+    /// Returns the size of the preprocessed frames.
+    pub fn output_size(&self) -> (u32, u32) {
+        self.output_size
+    }
+
+    /// Scales the output by an extra `factor`, on top of the configured size.
+    pub fn scaled(mut self, factor: f32) -> Self {
+        let (width, height) = self.output_size;
+        let size = (
+            even((width as f32 * factor).round() as u32).max(2),
+            even((height as f32 * factor).round() as u32).max(2),
+        );
+        self.size = Some(size);
+        self.output_size = size;
+        self
+    }
+
+    /// Rotates, crops and scales a decoded frame.
+    pub fn apply(&self, frame: RgbaImage) -> RgbaImage {
+        let frame = match self.rotation {
+            90 => imageops::rotate90(&frame),
+            180 => imageops::rotate180(&frame),
+            270 => imageops::rotate270(&frame),
+            _ => frame,
+        };
+        let frame = match self.crop {
+            Some((x, y, width, height)) => imageops::crop_imm(&frame, x, y, width, height).to_image(),
+            None => frame,
+        };
+        match self.size {
+            Some((width, height)) if (width, height) != frame.dimensions() => {
+                imageops::resize(&frame, width, height, self.filter)
+            }
+            _ => frame,
+        }
+    }
+}
+
+/// Reads the clockwise rotation the video at `path` should be displayed with, from the display
+/// matrix or the `rotate` tag of its video track.
+pub fn read_rotation(path: &Path) -> Result<u32, Box<dyn Error>> {
+    let input = ffmpeg::format::input(path)?;
+    let stream = input.streams()
+        .best(ffmpeg::media::Type::Video)
+        .ok_or("The input has no video track")?;
+
+    for side_data in stream.side_data() {
+        if side_data.kind() == ffmpeg::codec::packet::side_data::Type::DisplayMatrix
+            && let Some(rotation) = display_matrix_rotation(side_data.data())
+        {
+            return Ok(rotation);
+        }
+    }
+    let tag = stream.metadata().get("rotate").and_then(|value| value.parse::<f64>().ok());
+    Ok(snap_rotation(tag.unwrap_or(0.0)))
+}
+
+/// Returns the clockwise rotation stored in a display matrix of nine 16.16 fixed-point values,
+/// as FFmpeg's own autorotation reads it.
+fn display_matrix_rotation(data: &[u8]) -> Option<u32> {
+    let value = |index: usize| {
+        let bytes = data.get(index * 4..index * 4 + 4)?;
+        Some(i32::from_ne_bytes(bytes.try_into().ok()?) as f64 / 65536.0)
+    };
+    let (a, b, c, d) = (value(0)?, value(1)?, value(3)?, value(4)?);
+    let (scale_x, scale_y) = (a.hypot(c), b.hypot(d));
+    if scale_x == 0.0 || scale_y == 0.0 {
+        return None;
+    }
+    Some(snap_rotation((b / scale_y).atan2(a / scale_x).to_degrees()))
+}
+
+/// Snaps an angle in degrees to the nearest multiple of 90 degrees, within 0 to 270.
+fn snap_rotation(degrees: f64) -> u32 {
+    (((degrees / 90.0).round() as i64 * 90).rem_euclid(360)) as u32
+}
+
+/// Rounds a dimension down to an even number.
+fn even(value: u32) -> u32 {
+    value & !1
+}
@@ -0,0 +1,175 @@
+use serde::Deserialize;
+use std::{
+    error::Error,
+    path::{
+        Path,
+        PathBuf,
+    },
+};
+
+use crate::{
+    output::{
+        FrameSink,
+        FrameStep,
+        VideoSink,
+        animation::{
+            AnimationFormat,
+            AnimationSettings,
+        },
+        contact_sheet::ContactSheetSink,
+    },
+    video_processors::{
+        EffectSettings,
+        keyframes::KeyframeTrack,
+    },
+};
+
+/// Defines the file types a preview can be written as.
+#[allow(dead_code)]
+#[derive(Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PreviewFormat {
+    /// A small H.264 video.
+    Mp4,
+    /// A looping animated GIF.
+    Gif,
+    /// A grid of the frames in a single PNG image.
+    ContactSheet,
+}
+
+impl PreviewFormat {
+    /// Returns the extension of the preview file.
+    pub fn extension(self) -> &'static str {
+        match self {
+            PreviewFormat::Mp4 => "mp4",
+            PreviewFormat::Gif => "gif",
+            PreviewFormat::ContactSheet => "png",
+        }
+    }
+}
+
+/// Configuration for the preview mode, which renders a small part of the video quickly to try out
+/// settings.
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PreviewSettings {
+    /// If `true`, only the preview is rendered instead of the full video.
+    pub enabled: bool,
+    /// The start of the previewed time window, in milliseconds from the start of the video.
+    pub start_ms: f64,
+    /// The length of the previewed time window in milliseconds, none to run until the end.
+    pub duration_ms: Option<f64>,
+    /// The factor the frames are scaled by (0.0 to 1.0).
+    pub scale: f32,
+    /// Only every `frame_step`-th frame is written. The effect still runs on every frame, so the
+    /// timing of the trails is unchanged.
+    pub frame_step: usize,
+    pub format: PreviewFormat,
+    /// The number of frames per row of a contact sheet.
+    pub columns: u32,
+}
+
+impl Default for PreviewSettings {
+    fn default() -> Self {
+        PreviewSettings {
+            enabled: false,
+            start_ms: 0.0,
+            duration_ms: Some(5000.0),
+            scale: 0.25,
+            frame_step: 1,
+            format: PreviewFormat::Mp4,
+            columns: 4,
+        }
+    }
+}
+
+impl PreviewSettings {
+    /// Returns the end of the previewed time window in milliseconds, if it has one.
+    pub fn end_ms(&self) -> Option<f64> {
+        self.duration_ms.map(|duration_ms| self.start_ms + duration_ms)
+    }
+
+    /// Returns the file the preview of the render to `output` is written to,
+    /// `<output stem>_preview.<extension>` next to it. The frame number of an image sequence is
+    /// left out of the name.
+    pub fn output_path(&self, output: &Path) -> PathBuf {
+        let stem = output.file_stem().map(|stem| stem.to_string_lossy()).unwrap_or_default();
+        let stem = stem.split('%').next().unwrap_or_default().trim_end_matches(['_', '-', '.', ' ']);
+        let file_name = if stem.is_empty() {
+            format!("preview.{}", self.format.extension())
+        } else {
+            format!("{}_preview.{}", stem, self.format.extension())
+        };
+        output.with_file_name(file_name)
+    }
+
+    /// Creates the sink writing the preview frames of `width` by `height` pixels to `path`. GIF
+    /// previews are written with `animation`, like an animated output, so they look like the full
+    /// render. MP4 previews start at zero, however late the previewed window starts.
+    pub fn create_sink(
+        &self,
+        path: &Path,
+        width: u32,
+        height: u32,
+        animation: &AnimationSettings,
+    ) -> Result<Box<dyn FrameSink>, Box<dyn Error>> {
+        let sink: Box<dyn FrameSink> = match self.format {
+            PreviewFormat::Mp4 => Box::new(VideoSink::starting_at_zero(path, width, height)?),
+            PreviewFormat::Gif => animation.create_sink(path, AnimationFormat::Gif, false)?,
+            PreviewFormat::ContactSheet => Box::new(ContactSheetSink::new(PathBuf::from(path), self.columns)),
+        };
+        Ok(Box::new(FrameStep::new(sink, self.frame_step)))
+    }
+
+    /// Scales the sizes in pixels of the effect settings and of their keyframe `tracks` to the
+    /// preview resolution, so the preview looks like a scaled-down version of the full render.
+    pub fn scale_effect(&self, settings: &mut EffectSettings, tracks: &mut [KeyframeTrack]) {
+        for radius in &mut settings.glow.radii {
+            *radius *= self.scale;
+        }
+        for track in tracks.iter_mut().filter(|track| track.field.starts_with("glow.radii.")) {
+            for keyframe in &mut track.keyframes {
+                keyframe.value *= self.scale;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{
+        Rgba,
+        RgbaImage,
+    };
+    use std::fs::{
+        self,
+        File,
+    };
+    use video_rs::Time;
+
+    use super::*;
+
+    #[test]
+    fn gif_preview_drops_frames_above_the_animation_frame_rate() {
+        let path = std::env::temp_dir().join(format!("sticky_frame_preview_test_{}.gif", std::process::id()));
+        let preview = PreviewSettings { format: PreviewFormat::Gif, ..PreviewSettings::default() };
+        let animation = AnimationSettings { frame_rate: Some(10.0), ..AnimationSettings::default() };
+
+        // One second of frames at 30 fps
+        let mut sink = preview.create_sink(&path, 8, 8, &animation).unwrap();
+        for index in 0..30_u8 {
+            let frame = RgbaImage::from_pixel(8, 8, Rgba([index * 8, 0, 255 - index * 8, 255]));
+            sink.write(frame, Time::from_secs_f64(index as f64 / 30.0)).unwrap();
+        }
+        sink.finish().unwrap();
+        drop(sink);
+
+        let mut decoder = gif::DecodeOptions::new().read_info(File::open(&path).unwrap()).unwrap();
+        let mut frame_count = 0;
+        while decoder.read_next_frame().unwrap().is_some() {
+            frame_count += 1;
+        }
+        fs::remove_file(&path).unwrap();
+        assert_eq!(frame_count, 10);
+    }
+}
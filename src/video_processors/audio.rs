@@ -0,0 +1,347 @@
+use serde::Deserialize;
+use std::{
+    error::Error,
+    f32::consts::PI,
+    path::Path,
+};
+use video_rs::ffmpeg;
+
+use crate::video_processors::EffectSettings;
+
+/// The sample rate the audio is analyzed at.
+const SAMPLE_RATE: u32 = 44_100;
+/// The time between two values of an envelope, in milliseconds.
+const HOP_MS: f64 = 10.0;
+/// The number of samples in each FFT window, must be a power of two.
+const FFT_SIZE: usize = 2048;
+
+/// Defines the audio features that can drive a setting.
+#[derive(Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AudioFeature {
+    /// The loudness of the audio.
+    Rms,
+    /// How suddenly the spectrum changes, peaking on beats and note onsets.
+    Onset,
+    /// The energy within the frequency band `[low_hz, high_hz]`.
+    Band([f32; 2]),
+}
+
+/// Defines how an audio feature changes the value of a field.
+#[derive(Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ModulationMode {
+    /// Multiplies the value by `1.0 + gain * feature`, so `gain = 1.0` doubles it at the peak.
+    #[default]
+    Scale,
+    /// Adds `gain * feature` to the value, in the unit of the field.
+    Add,
+}
+
+/// Drives one numeric field of the `EffectSettings` with a feature of the audio track.
+#[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AudioModulation {
+    /// The path of the driven field, for example `colored.tracer_opacity`.
+    pub field: String,
+    pub feature: AudioFeature,
+    /// How the feature changes the value of the field.
+    #[serde(default)]
+    pub mode: ModulationMode,
+    /// How strongly the normalized feature (0.0 to 1.0) changes the value of the field: the
+    /// fraction of the value added at the peak when scaling, or the amount added when adding.
+    #[serde(default = "default_gain")]
+    pub gain: f32,
+    /// The time constant of the smoothing applied to the feature, in milliseconds.
+    #[serde(default)]
+    pub smoothing_ms: f32,
+    /// The lowest value the field is driven to.
+    pub min: Option<f32>,
+    /// The highest value the field is driven to.
+    pub max: Option<f32>,
+}
+
+fn default_gain() -> f32 {
+    1.0
+}
+
+/// The envelopes of a set of modulations over the whole audio track, sampled every `HOP_MS`.
+pub struct AudioEnvelopes {
+    /// The timestamp of the first audio sample, in milliseconds.
+    start_ms: f64,
+    /// One normalized and smoothed envelope per modulation.
+    envelopes: Vec<Vec<f32>>,
+}
+
+impl AudioEnvelopes {
+    /// Decodes the audio track of the video at `path` and computes the envelope of every
+    /// modulation.
+    pub fn analyze(path: &Path, modulations: &[AudioModulation]) -> Result<Self, Box<dyn Error>> {
+        let mut analyzer = EnvelopeAnalyzer::new(modulations);
+        let start_ms = decode_audio(path, &mut analyzer)?;
+        let mut envelopes = analyzer.envelopes;
+        for (modulation, envelope) in modulations.iter().zip(&mut envelopes) {
+            normalize(envelope);
+            smooth(envelope, modulation.smoothing_ms);
+        }
+
+        Ok(AudioEnvelopes { start_ms, envelopes })
+    }
+
+    /// Returns the envelope of the modulation at `index` at `time_ms` (0.0 to 1.0), 0.0 outside of
+    /// the audio track.
+    pub fn value_at(&self, index: usize, time_ms: f64) -> f32 {
+        let position = ((time_ms - self.start_ms) / HOP_MS).floor() - 1.0;
+        if position < 0.0 {
+            return 0.0;
+        }
+        self.envelopes[index].get(position as usize).copied().unwrap_or(0.0)
+    }
+
+    /// Drives the fields of `settings` with the envelopes at `time_ms`. Each field is scaled by or
+    /// added the envelope times the gain, then clamped to the configured range.
+    pub fn modulate(&self, modulations: &[AudioModulation], settings: &mut EffectSettings, time_ms: f64) {
+        for (index, modulation) in modulations.iter().enumerate() {
+            let envelope = self.value_at(index, time_ms);
+            if let Some(field) = settings.numeric_field_mut(&modulation.field) {
+                let mut value = match modulation.mode {
+                    ModulationMode::Scale => field.get() * (1.0 + modulation.gain * envelope),
+                    ModulationMode::Add => field.get() + modulation.gain * envelope,
+                };
+                if let Some(min) = modulation.min {
+                    value = value.max(min);
+                }
+                if let Some(max) = modulation.max {
+                    value = value.min(max);
+                }
+                field.set(value);
+            }
+        }
+    }
+}
+
+/// Computes the raw envelopes of a set of modulations from the audio samples as they are decoded,
+/// keeping only the samples the next FFT window needs.
+struct EnvelopeAnalyzer<'a> {
+    modulations: &'a [AudioModulation],
+    /// The number of samples between two envelope values.
+    hop: usize,
+    needs_spectrum: bool,
+    twiddles: Vec<(f32, f32)>,
+    /// The samples not yet analyzed, preceded by up to `FFT_SIZE` analyzed ones.
+    samples: Vec<f32>,
+    /// The number of analyzed samples at the start of `samples`.
+    analyzed: usize,
+    previous_spectrum: Vec<f32>,
+    /// One envelope per modulation, neither normalized nor smoothed.
+    envelopes: Vec<Vec<f32>>,
+}
+
+impl<'a> EnvelopeAnalyzer<'a> {
+    fn new(modulations: &'a [AudioModulation]) -> Self {
+        EnvelopeAnalyzer {
+            modulations,
+            hop: (SAMPLE_RATE as f64 * HOP_MS / 1000.0) as usize,
+            needs_spectrum: modulations.iter().any(|modulation| modulation.feature != AudioFeature::Rms),
+            twiddles: fft_twiddles(FFT_SIZE),
+            samples: Vec::new(),
+            analyzed: 0,
+            previous_spectrum: vec![0.0; FFT_SIZE / 2],
+            envelopes: vec![Vec::new(); modulations.len()],
+        }
+    }
+
+    /// Appends decoded samples and computes the envelope values of every hop they complete.
+    fn push(&mut self, samples: &[f32]) {
+        self.samples.extend_from_slice(samples);
+        while self.samples.len() - self.analyzed >= self.hop {
+            // Each value describes the audio up to the end of its hop
+            let end = self.analyzed + self.hop;
+            self.analyze_hop(end);
+            self.analyzed = end;
+        }
+
+        // Drop the samples that have left the FFT window
+        let unused = self.analyzed.saturating_sub(FFT_SIZE);
+        self.samples.drain(..unused);
+        self.analyzed -= unused;
+    }
+
+    /// Computes the envelope values of the hop ending at `end` in `samples`.
+    fn analyze_hop(&mut self, end: usize) {
+        let hop_samples = &self.samples[end - self.hop..end];
+        let rms = (hop_samples.iter().map(|sample| sample * sample).sum::<f32>() / self.hop as f32).sqrt();
+        let spectrum = if self.needs_spectrum {
+            magnitude_spectrum(&self.samples[end.saturating_sub(FFT_SIZE)..end], &self.twiddles)
+        } else {
+            Vec::new()
+        };
+
+        for (modulation, envelope) in self.modulations.iter().zip(&mut self.envelopes) {
+            let value = match modulation.feature {
+                AudioFeature::Rms => rms,
+                AudioFeature::Onset => {
+                    // Spectral flux: the summed increase of every frequency bin
+                    spectrum.iter()
+                        .zip(&self.previous_spectrum)
+                        .map(|(magnitude, previous)| (magnitude - previous).max(0.0))
+                        .sum()
+                }
+                AudioFeature::Band([low_hz, high_hz]) => {
+                    let bin_hz = SAMPLE_RATE as f32 / FFT_SIZE as f32;
+                    let low = (low_hz / bin_hz).floor().max(0.0) as usize;
+                    let high = ((high_hz / bin_hz).ceil() as usize).min(spectrum.len());
+                    spectrum[low.min(high)..high].iter().map(|magnitude| magnitude * magnitude).sum::<f32>().sqrt()
+                }
+            };
+            envelope.push(value);
+        }
+        if self.needs_spectrum {
+            self.previous_spectrum = spectrum;
+        }
+    }
+}
+
+/// Decodes the best audio track of the video at `path` to mono samples at `SAMPLE_RATE` and feeds
+/// them to `analyzer`. Returns the timestamp of the first sample in milliseconds.
+fn decode_audio(path: &Path, analyzer: &mut EnvelopeAnalyzer) -> Result<f64, Box<dyn Error>> {
+    let mut input = ffmpeg::format::input(path)?;
+    let stream = input.streams()
+        .best(ffmpeg::media::Type::Audio)
+        .ok_or("The input has no audio track")?;
+    let stream_index = stream.index();
+    let time_base = f64::from(stream.time_base());
+    let context = ffmpeg::codec::context::Context::from_parameters(stream.parameters())?;
+    let mut decoder = context.decoder().audio()?;
+    let mut resampler = decoder.resampler(
+        ffmpeg::format::Sample::F32(ffmpeg::format::sample::Type::Packed),
+        ffmpeg::ChannelLayout::MONO,
+        SAMPLE_RATE,
+    )?;
+
+    let mut start_ms = None;
+    for (stream, packet) in input.packets() {
+        if stream.index() != stream_index {
+            continue;
+        }
+        decoder.send_packet(&packet)?;
+        receive_audio(&mut decoder, &mut resampler, time_base, analyzer, &mut start_ms)?;
+    }
+    decoder.send_eof()?;
+    receive_audio(&mut decoder, &mut resampler, time_base, analyzer, &mut start_ms)?;
+
+    // The resampler holds back a few samples to filter across frames
+    let mut flushed = ffmpeg::frame::Audio::empty();
+    resampler.flush(&mut flushed)?;
+    if flushed.samples() > 0 {
+        analyzer.push(flushed.plane::<f32>(0));
+    }
+
+    Ok(start_ms.unwrap_or(0.0))
+}
+
+/// Receives every decoded audio frame and feeds it, resampled, to `analyzer`.
+fn receive_audio(
+    decoder: &mut ffmpeg::decoder::Audio,
+    resampler: &mut ffmpeg::software::resampling::Context,
+    time_base: f64,
+    analyzer: &mut EnvelopeAnalyzer,
+    start_ms: &mut Option<f64>,
+) -> Result<(), ffmpeg::Error> {
+    let mut decoded = ffmpeg::frame::Audio::empty();
+    while decoder.receive_frame(&mut decoded).is_ok() {
+        start_ms.get_or_insert(decoded.timestamp().map_or(0.0, |timestamp| timestamp as f64 * time_base * 1000.0));
+        let mut resampled = ffmpeg::frame::Audio::empty();
+        resampler.run(&decoded, &mut resampled)?;
+        analyzer.push(resampled.plane::<f32>(0));
+    }
+    Ok(())
+}
+
+/// Scales an envelope so its peak is 1.0.
+fn normalize(envelope: &mut [f32]) {
+    let peak = envelope.iter().copied().fold(0.0, f32::max);
+    if peak > 0.0 {
+        envelope.iter_mut().for_each(|value| *value /= peak);
+    }
+}
+
+/// Smooths an envelope with a one-pole low-pass filter with a time constant of `smoothing_ms`.
+fn smooth(envelope: &mut [f32], smoothing_ms: f32) {
+    if smoothing_ms <= 0.0 {
+        return;
+    }
+    let alpha = 1.0 - (-(HOP_MS as f32) / smoothing_ms).exp();
+    let mut smoothed = 0.0;
+    for value in envelope.iter_mut() {
+        smoothed += (*value - smoothed) * alpha;
+        *value = smoothed;
+    }
+}
+
+/// Returns the magnitudes of the lower half of the spectrum of a Hann-windowed block of samples.
+/// Blocks shorter than `FFT_SIZE` are padded with silence at the start.
+fn magnitude_spectrum(block: &[f32], twiddles: &[(f32, f32)]) -> Vec<f32> {
+    let mut re = vec![0.0; FFT_SIZE];
+    let mut im = vec![0.0; FFT_SIZE];
+    let offset = FFT_SIZE - block.len();
+    for (i, sample) in block.iter().enumerate() {
+        let n = offset + i;
+        let hann = 0.5 - 0.5 * (2.0 * PI * n as f32 / (FFT_SIZE - 1) as f32).cos();
+        re[n] = sample * hann;
+    }
+    fft(&mut re, &mut im, twiddles);
+    re.iter()
+        .zip(&im)
+        .take(FFT_SIZE / 2)
+        .map(|(re, im)| (re * re + im * im).sqrt())
+        .collect()
+}
+
+/// Precomputes the twiddle factors `(cos, sin)` of an FFT of length `size`.
+fn fft_twiddles(size: usize) -> Vec<(f32, f32)> {
+    (0..size / 2)
+        .map(|k| {
+            let (sin, cos) = (-2.0 * PI * k as f32 / size as f32).sin_cos();
+            (cos, sin)
+        })
+        .collect()
+}
+
+/// An in-place iterative radix-2 FFT over the real and imaginary parts of a signal.
+fn fft(re: &mut [f32], im: &mut [f32], twiddles: &[(f32, f32)]) {
+    let n = re.len();
+
+    // Reorder the samples into bit-reversed order
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+
+    let mut len = 2;
+    while len <= n {
+        let stride = n / len;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (cos, sin) = twiddles[k * stride];
+                let (a, b) = (start + k, start + k + len / 2);
+                let t_re = re[b] * cos - im[b] * sin;
+                let t_im = re[b] * sin + im[b] * cos;
+                re[b] = re[a] - t_re;
+                im[b] = im[a] - t_im;
+                re[a] += t_re;
+                im[a] += t_im;
+            }
+        }
+        len <<= 1;
+    }
+}
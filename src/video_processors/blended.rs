@@ -1,15 +1,23 @@
 use image::RgbaImage;
+use serde::Deserialize;
 
 use crate::video_processors::{
-    CalculatedDecay,
     EffectSettings,
+    EffectState,
+    decay::{
+        DecayCurve,
+        DecayPass,
+    },
     utils::{
-        create_motion_mask,
-        move_towards,
+        BlendMode,
+        MotionMask,
+        blend_pixel,
     },
 };
 
 /// Configuration for the `Blended` effect.
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct BlendedSettings {
     /// The blend factor for combining the canvas and the current frame (0.0 to 1.0).
     /// - `0.0` makes the live action completely transparent (ghostly).
@@ -19,6 +27,22 @@ pub struct BlendedSettings {
     /// The duration it takes for a static background to fade in to full clarity, in milliseconds.
     /// If `None`, the background will not fade in.
     pub tracer_duration_ms: Option<u32>,
+    /// The shape of the fade over `tracer_duration_ms`.
+    pub decay_curve: DecayCurve,
+    /// How the ghostly canvas is composited onto the live action, at an opacity of
+    /// `1.0 - blend_factor`.
+    pub blend_mode: BlendMode,
+}
+
+impl Default for BlendedSettings {
+    fn default() -> Self {
+        BlendedSettings {
+            blend_factor: 0.5,
+            tracer_duration_ms: Some(5000),
+            decay_curve: DecayCurve::Linear,
+            blend_mode: BlendMode::Normal,
+        }
+    }
 }
 
 /// Processes a single frame for the `Blended` effect.
@@ -26,40 +50,56 @@ pub struct BlendedSettings {
 /// persistent canvas. The canvas gradually clarifies in static areas, creating a fade-in effect
 /// for the background.
 pub fn process_blended_frame(
-    canvas: &mut RgbaImage,
+    state: &mut EffectState,
     current_frame: &RgbaImage,
-    prev_frame: &RgbaImage,
+    motion_mask: &MotionMask,
     settings: &EffectSettings,
-    decay: &CalculatedDecay,
     should_update_canvas: bool,
+    timestamp_ms: f64,
 ) -> RgbaImage {
-    let motion_thresh = (255.0 * settings.motion_threshold_percent) as i16;
+    let canvas = &mut state.canvas;
     let (width, height) = canvas.dimensions();
-    let motion_mask = create_motion_mask(current_frame, prev_frame, motion_thresh);
     let mut output_frame = RgbaImage::new(width, height);
-    let clarify_amount = decay.blended;
+    let clarify_pass = DecayPass::new(
+        settings.blended.decay_curve,
+        settings.blended.tracer_duration_ms,
+        timestamp_ms,
+        state.last_decay_ms,
+    );
 
     for (x, y, canvas_pixel) in canvas.enumerate_pixels_mut() {
-        if should_update_canvas && !motion_mask[(y * width + x) as usize] && clarify_amount > 0.0 {
+        let index = (y * width + x) as usize;
+        if let Some(ages) = state.trail_ages.as_mut() {
+            // The trail here is the part of the canvas that lags behind moving objects
+            if motion_mask.is_moving(index) {
+                ages.stamp(index, timestamp_ms);
+            } else if ages.is_expired(index, timestamp_ms, settings.max_trail_age_ms) {
+                *canvas_pixel = image::Rgba(current_frame.get_pixel(x, y).0.map(f32::from));
+                ages.clear(index);
+            }
+        }
+
+        if should_update_canvas && !motion_mask.is_moving(index) && clarify_pass.is_active() {
             let current_pixel = *current_frame.get_pixel(x, y);
+            let pixel_decay = clarify_pass.for_pixel(state.trail_ages.as_ref(), index);
             *canvas_pixel = image::Rgba([
-                move_towards(canvas_pixel[0], current_pixel[0], clarify_amount),
-                move_towards(canvas_pixel[1], current_pixel[1], clarify_amount),
-                move_towards(canvas_pixel[2], current_pixel[2], clarify_amount),
-                255,
+                pixel_decay.apply(canvas_pixel[0], current_pixel[0] as f32),
+                pixel_decay.apply(canvas_pixel[1], current_pixel[1] as f32),
+                pixel_decay.apply(canvas_pixel[2], current_pixel[2] as f32),
+                255.0,
             ]);
         }
 
         let current_pixel = *current_frame.get_pixel(x, y);
         let clarity = settings.blended.blend_factor;
 
-        // Blend the canvas and the current frame to create the final output
-        *output_frame.get_pixel_mut(x, y) = image::Rgba([
-            (canvas_pixel[0] as f32 * (1.0 - clarity) + current_pixel[0] as f32 * clarity) as u8,
-            (canvas_pixel[1] as f32 * (1.0 - clarity) + current_pixel[1] as f32 * clarity) as u8,
-            (canvas_pixel[2] as f32 * (1.0 - clarity) + current_pixel[2] as f32 * clarity) as u8,
-            255,
-        ]);
+        // Blend the canvas onto the current frame to create the final output
+        *output_frame.get_pixel_mut(x, y) =
+            blend_pixel(&current_pixel, canvas_pixel, settings.blended.blend_mode, 1.0 - clarity);
+    }
+
+    if should_update_canvas {
+        state.last_decay_ms = Some(timestamp_ms);
     }
 
     output_frame
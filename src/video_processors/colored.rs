@@ -1,95 +1,278 @@
-use image::{
-    Pixel,
-    RgbaImage,
-};
+use image::RgbaImage;
+use serde::Deserialize;
 
 use crate::video_processors::{
-    CalculatedDecay,
     EffectSettings,
+    EffectState,
+    decay::{
+        DecayCurve,
+        DecayPass,
+    },
+    glow::apply_glow,
+    gradient::{
+        Gradient,
+        GradientIndex,
+        deserialize_hex_color,
+    },
     utils::{
-        create_motion_mask,
+        BlendMode,
+        MotionMask,
+        blend_pixel,
         hsv_to_rgb,
-        move_towards,
+        luminance,
     },
+    trail_age::TrailAges,
 };
 
 /// Configuration for the `Colored` effect.
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct ColoredSettings {
-    /// The static color of the trails if `rainbow_mode` is `false`.
+    /// The static color of the trails if `rainbow_mode` is `false` and no `gradient` is set.
+    #[serde(deserialize_with = "deserialize_hex_color")]
     pub color: image::Rgba<u8>,
     /// If `true`, the trail color will cycle through the rainbow.
     pub rainbow_mode: bool,
-    /// The speed at which the rainbow color cycles. Higher is faster. `1.0` is a good starting point.
+    /// The speed at which the rainbow color cycles, in degrees of hue per second of video.
+    /// Also used as the cycling speed of a `gradient` indexed by `GradientIndex::Time`.
     pub rainbow_speed: f32,
+    /// How the rainbow hue is picked for each trail pixel.
+    pub hue_mode: HueMode,
+    /// A multi-stop gradient or palette to color the trails with, overrides `color` and `rainbow_mode`.
+    pub gradient: Option<Gradient>,
+    /// What drives the position along the `gradient`.
+    pub gradient_index: GradientIndex,
     /// The opacity of the stamped trail (0.0 to 1.0).
     /// - `0.0` makes the trail completely transparent.
     /// - `1.0` makes the trail completely solid.
     pub tracer_opacity: f32,
     /// The duration a trail should last, in milliseconds, none for permanent.
     pub tracer_duration_ms: Option<u32>,
+    /// The shape of the fade over `tracer_duration_ms`.
+    pub decay_curve: DecayCurve,
+    /// How the trails are composited onto the frame. `BlendMode::Add` gives glowing light trails.
+    pub blend_mode: BlendMode,
+}
+
+impl Default for ColoredSettings {
+    fn default() -> Self {
+        ColoredSettings {
+            color: image::Rgba([255, 255, 255, 255]),
+            rainbow_mode: true,
+            rainbow_speed: 150.0,
+            hue_mode: HueMode::Cycle,
+            gradient: None,
+            gradient_index: GradientIndex::Age,
+            tracer_opacity: 1.0,
+            tracer_duration_ms: Some(5000),
+            decay_curve: DecayCurve::Linear,
+            blend_mode: BlendMode::Add,
+        }
+    }
+}
+
+/// Defines how the rainbow hue of each trail pixel is picked.
+#[derive(Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HueMode {
+    /// Every pixel takes the cycling hue.
+    Cycle,
+    /// The hue shifts by `spread` degrees across the frame, in the direction `angle` in degrees
+    /// (`0.0` runs left to right, `90.0` top to bottom), on top of the cycling hue.
+    Spatial { spread: f32, angle: f32 },
+    /// The hue moves back and forth between the hues `from` and `to` instead of cycling.
+    PingPong { from: f32, to: f32 },
+    /// The hue shifts by up to `spread` degrees with the strength of the motion at each pixel, on
+    /// top of the cycling hue.
+    Motion { spread: f32 },
+}
+
+impl HueMode {
+    /// Returns the hue shared by every pixel for the rainbow `phase`, in degrees. The phase is
+    /// wrapped before it is narrowed to `f32`, which couldn't hold the small steps of a large one.
+    fn base_hue(self, phase: f64) -> f32 {
+        match self {
+            HueMode::PingPong { from, to } => {
+                let range = (to - from).abs();
+                if range == 0.0 {
+                    return from;
+                }
+                let position = phase.rem_euclid(2.0 * range as f64) as f32;
+                let distance = if position < range { position } else { 2.0 * range - position };
+                from + distance * (to - from).signum()
+            }
+            _ => phase.rem_euclid(360.0) as f32,
+        }
+    }
+
+    /// Returns the hue offset of a single pixel, from its position within the frame (0.0 to 1.0 on
+    /// both axes) and the `intensity` of its motion (0.0 to 1.0).
+    fn pixel_offset(self, x: f32, y: f32, intensity: f32) -> f32 {
+        match self {
+            HueMode::Spatial { spread, angle } => {
+                // Project the pixel onto the direction, normalized so the frame spans 0.0 to 1.0
+                let (sin, cos) = angle.to_radians().sin_cos();
+                let start = cos.min(0.0) + sin.min(0.0);
+                let extent = cos.abs() + sin.abs();
+                spread * (x * cos + y * sin - start) / extent
+            }
+            HueMode::Motion { spread } => spread * intensity,
+            HueMode::Cycle | HueMode::PingPong { .. } => 0.0,
+        }
+    }
 }
 
 /// Processes a single frame for the `Colored` effect.
 /// This effect creates a colored trail where motion is detected. The trail can be a static color
 /// or a cycling rainbow. The opacity and duration of the trail can be configured.
 pub fn process_colored_frame(
-    canvas: &mut RgbaImage,
+    state: &mut EffectState,
     current_frame: &RgbaImage,
-    prev_frame: &RgbaImage,
+    motion_mask: &MotionMask,
     settings: &EffectSettings,
-    decay: &CalculatedDecay,
     should_trail: bool,
-    rainbow_hue: &mut f32,
+    timestamp_ms: f64,
 ) -> RgbaImage {
-    let motion_thresh = (255.0 * settings.motion_threshold_percent) as i16;
-    let (width, _height) = canvas.dimensions();
-    let motion_mask = create_motion_mask(current_frame, prev_frame, motion_thresh);
-    let decay_amount = decay.colored;
+    // Advance the rainbow by the time since the previous frame, so it cycles at the same speed for
+    // any frame rate or frame step
+    let elapsed_s = state.last_decay_ms.map_or(0.0, |last_ms| (timestamp_ms - last_ms) / 1000.0);
+    state.rainbow_hue += settings.colored.rainbow_speed as f64 * elapsed_s;
+
+    let canvas = &mut state.canvas;
+    let (width, height) = canvas.dimensions();
+    let decay_pass = DecayPass::new(
+        settings.colored.decay_curve,
+        settings.colored.tracer_duration_ms,
+        timestamp_ms,
+        state.last_decay_ms,
+    );
+    let lifetime_ms = settings.colored.tracer_duration_ms.or(settings.max_trail_age_ms);
+
+    // With an age-indexed gradient, the canvas alpha holds the "heat" of the trail, which decays
+    // over time. The trail color is picked from the gradient by the exact trail age if it is
+    // tracked, or by the remaining heat otherwise.
+    let age_gradient = settings
+        .colored
+        .gradient
+        .as_ref()
+        .filter(|_| settings.colored.gradient_index == GradientIndex::Age);
 
     // Decay the canvas pixels over time
-    if decay_amount > 0.0 {
-        for pixel in canvas.pixels_mut() {
+    for (x, y, pixel) in canvas.enumerate_pixels_mut() {
+        let index = (y * width + x) as usize;
+        if let Some(ages) = state.trail_ages.as_mut()
+            && ages.is_expired(index, timestamp_ms, settings.max_trail_age_ms)
+        {
+            // Discard trails that outlived the maximum age
+            *pixel = image::Rgba([0.0, 0.0, 0.0, 0.0]);
+            ages.clear(index);
+        } else if let Some(gradient) = age_gradient {
+            let heat = decay_pass.for_pixel(state.trail_ages.as_ref(), index).apply(pixel[3], 0.0);
+            let position = age_position(state.trail_ages.as_ref(), index, timestamp_ms, lifetime_ms, heat);
+            *pixel = heat_to_color(gradient, heat, position);
+        } else if decay_pass.is_active() {
+            let pixel_decay = decay_pass.for_pixel(state.trail_ages.as_ref(), index);
             *pixel = image::Rgba([
-                move_towards(pixel[0], 0, decay_amount),
-                move_towards(pixel[1], 0, decay_amount),
-                move_towards(pixel[2], 0, decay_amount),
-                255,
+                pixel_decay.apply(pixel[0], 0.0),
+                pixel_decay.apply(pixel[1], 0.0),
+                pixel_decay.apply(pixel[2], 0.0),
+                255.0,
             ]);
         }
     }
+    state.last_decay_ms = Some(timestamp_ms);
 
     // Add new trails in areas of motion
     if should_trail {
-        let trail_color = if settings.colored.rainbow_mode {
-            let (r, g, b) = hsv_to_rgb(*rainbow_hue, 1.0, 1.0);
-            *rainbow_hue = (*rainbow_hue + settings.colored.rainbow_speed) % 360.0;
-            image::Rgba([r, g, b, 255])
-        } else {
-            settings.colored.color
-        };
+        let hue_mode = settings.colored.hue_mode;
+        let base_hue = hue_mode.base_hue(state.rainbow_hue);
 
+        let opacity = settings.colored.tracer_opacity;
         for (x, y, canvas_pixel) in canvas.enumerate_pixels_mut() {
-            if motion_mask[(y * width + x) as usize] {
-                let opacity = settings.colored.tracer_opacity;
-                canvas_pixel.blend(&image::Rgba([
-                    (canvas_pixel[0] as f32 * (1.0 - opacity) + trail_color[0] as f32 * opacity) as u8,
-                    (canvas_pixel[1] as f32 * (1.0 - opacity) + trail_color[1] as f32 * opacity) as u8,
-                    (canvas_pixel[2] as f32 * (1.0 - opacity) + trail_color[2] as f32 * opacity) as u8,
-                    255,
-                ]));
+            let index = (y * width + x) as usize;
+            if !motion_mask.is_moving(index) {
+                continue;
             }
+            if let Some(ages) = state.trail_ages.as_mut() {
+                ages.stamp(index, timestamp_ms);
+            }
+
+            if let Some(gradient) = age_gradient {
+                let heat = canvas_pixel[3] * (1.0 - opacity) + 255.0 * opacity;
+                let position = age_position(state.trail_ages.as_ref(), index, timestamp_ms, lifetime_ms, heat);
+                *canvas_pixel = heat_to_color(gradient, heat, position);
+                continue;
+            }
+
+            let hue = base_hue
+                + hue_mode.pixel_offset(x as f32 / width as f32, y as f32 / height as f32, motion_mask.intensity(index));
+            let trail_color = match (&settings.colored.gradient, settings.colored.gradient_index) {
+                (Some(gradient), GradientIndex::Luminance) => {
+                    gradient.sample(luminance(current_frame.get_pixel(x, y)) / 255.0)
+                }
+                (Some(gradient), _) => gradient.sample(hue.rem_euclid(360.0) / 360.0),
+                (None, _) if settings.colored.rainbow_mode => {
+                    let (r, g, b) = hsv_to_rgb(hue.rem_euclid(360.0), 1.0, 1.0);
+                    image::Rgba([r, g, b, 255])
+                }
+                (None, _) => settings.colored.color,
+            };
+            *canvas_pixel = image::Rgba([
+                canvas_pixel[0] * (1.0 - opacity) + trail_color[0] as f32 * opacity,
+                canvas_pixel[1] * (1.0 - opacity) + trail_color[1] as f32 * opacity,
+                canvas_pixel[2] * (1.0 - opacity) + trail_color[2] as f32 * opacity,
+                255.0,
+            ]);
         }
     }
 
-    // Combine the canvas and the current frame to create the final output
+    // Combine the canvas and the current frame to create the final output. The canvas holds trail
+    // light that fades towards black, so its brightest channel is used as the trail coverage.
     let mut output_frame = current_frame.clone();
     for (x, y, output_pixel) in output_frame.enumerate_pixels_mut() {
         let canvas_pixel = canvas.get_pixel(x, y);
-        let r = (output_pixel[0] as u16 + canvas_pixel[0] as u16).min(255) as u8;
-        let g = (output_pixel[1] as u16 + canvas_pixel[1] as u16).min(255) as u8;
-        let b = (output_pixel[2] as u16 + canvas_pixel[2] as u16).min(255) as u8;
-        *output_pixel = image::Rgba([r, g, b, 255]);
+        let coverage = canvas_pixel[0].max(canvas_pixel[1]).max(canvas_pixel[2]) / 255.0;
+        if coverage <= 0.0 {
+            continue;
+        }
+        let trail_color = image::Rgba([
+            canvas_pixel[0] / coverage,
+            canvas_pixel[1] / coverage,
+            canvas_pixel[2] / coverage,
+            255.0,
+        ]);
+        *output_pixel = blend_pixel(output_pixel, &trail_color, settings.colored.blend_mode, coverage.min(1.0));
     }
 
+    apply_glow(&mut output_frame, canvas, &settings.glow);
+
     output_frame
 }
+
+/// Computes the position along an age-indexed gradient. The exact trail age is used when it is
+/// tracked and the trail has a known lifetime, otherwise the position follows the remaining heat.
+fn age_position(
+    ages: Option<&TrailAges>,
+    index: usize,
+    timestamp_ms: f64,
+    lifetime_ms: Option<u32>,
+    heat: f32,
+) -> f32 {
+    match (ages.and_then(|ages| ages.age_ms(index, timestamp_ms)), lifetime_ms) {
+        (Some(age), Some(lifetime)) if lifetime > 0 => (age / lifetime as f64) as f32,
+        _ => 1.0 - heat / 255.0,
+    }
+}
+
+/// Converts the heat of an age-indexed trail into its color. The color is sampled from the
+/// gradient at `position` and dims as the heat decays.
+fn heat_to_color(gradient: &Gradient, heat: f32, position: f32) -> image::Rgba<f32> {
+    let strength = heat / 255.0;
+    let color = gradient.sample(position);
+    image::Rgba([
+        color[0] as f32 * strength,
+        color[1] as f32 * strength,
+        color[2] as f32 * strength,
+        heat,
+    ])
+}
@@ -0,0 +1,145 @@
+use serde::Deserialize;
+
+use crate::video_processors::{
+    trail_age::TrailAges,
+    utils::move_towards,
+};
+
+/// Defines how a trail fades over its `tracer_duration_ms`.
+/// Every curve reaches zero when the duration is over, only the shape of the fade differs.
+#[allow(dead_code)]
+#[derive(Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DecayCurve {
+    /// Fades by a constant amount per millisecond.
+    Linear,
+    /// Halves the strength of the trail every `half_life_ms`, and cuts it at the end of the duration.
+    Exponential { half_life_ms: u32 },
+    /// Fades slowly at first and speeds up towards the end.
+    EaseIn,
+    /// Fades quickly at first and slows down towards the end.
+    EaseOut,
+    /// Fades slowly at both ends and fastest in the middle.
+    Smoothstep,
+    /// Stays at full strength for `hold_fraction` of the duration (0.0 to 1.0), then fades linearly.
+    HoldThenFade { hold_fraction: f32 },
+}
+
+impl DecayCurve {
+    /// Returns the remaining strength (1.0 to 0.0) of a trail that is `age_ms` old.
+    pub fn strength(&self, age_ms: f64, duration_ms: u32) -> f32 {
+        if age_ms <= 0.0 {
+            return 1.0;
+        }
+        if age_ms >= duration_ms as f64 {
+            return 0.0;
+        }
+        let t = (age_ms / duration_ms as f64) as f32;
+        match *self {
+            DecayCurve::Linear => 1.0 - t,
+            DecayCurve::Exponential { half_life_ms } => {
+                if half_life_ms == 0 {
+                    return 0.0;
+                }
+                0.5_f64.powf(age_ms / half_life_ms as f64) as f32
+            }
+            DecayCurve::EaseIn => 1.0 - t * t,
+            DecayCurve::EaseOut => (1.0 - t) * (1.0 - t),
+            DecayCurve::Smoothstep => 1.0 - t * t * (3.0 - 2.0 * t),
+            DecayCurve::HoldThenFade { hold_fraction } => {
+                let hold = hold_fraction.clamp(0.0, 1.0);
+                if t <= hold {
+                    1.0
+                } else {
+                    1.0 - (t - hold) / (1.0 - hold)
+                }
+            }
+        }
+    }
+}
+
+/// The decay to apply to a single canvas pixel during a decay pass.
+#[derive(Clone, Copy)]
+pub enum PixelDecay {
+    /// Move towards the target by a fixed amount.
+    Step(f32),
+    /// Keep this fraction of the distance to the target.
+    Retain(f32),
+}
+
+impl PixelDecay {
+    /// Applies the decay to a `current` channel value fading towards `target`.
+    pub fn apply(self, current: f32, target: f32) -> f32 {
+        match self {
+            PixelDecay::Step(amount) => move_towards(current, target, amount),
+            PixelDecay::Retain(retained) => target + (current - target) * retained,
+        }
+    }
+}
+
+/// Describes one decay pass over the canvas of an effect.
+pub struct DecayPass {
+    curve: DecayCurve,
+    duration_ms: Option<u32>,
+    /// The amount the linear curve fades during this pass.
+    step: f32,
+    timestamp_ms: f64,
+    elapsed_ms: f64,
+}
+
+impl DecayPass {
+    /// Creates a decay pass at `timestamp_ms`, where `last_pass_ms` is the time of the previous
+    /// pass. The fade covers the actual time between both passes, so trails last their
+    /// `duration_ms` for any frame rate, including variable frame rates.
+    pub fn new(
+        curve: DecayCurve,
+        duration_ms: Option<u32>,
+        timestamp_ms: f64,
+        last_pass_ms: Option<f64>,
+    ) -> Self {
+        let elapsed_ms = last_pass_ms.map_or(0.0, |last| (timestamp_ms - last).max(0.0));
+        DecayPass {
+            curve,
+            duration_ms,
+            step: linear_step(duration_ms, elapsed_ms),
+            timestamp_ms,
+            elapsed_ms,
+        }
+    }
+
+    /// Returns `true` if the pass changes the canvas at all.
+    pub fn is_active(&self) -> bool {
+        self.duration_ms.is_some()
+    }
+
+    /// Returns the decay for the pixel at `index`. Non-linear curves follow the exact trail age,
+    /// and pixels without a recorded trail are considered fully faded.
+    pub fn for_pixel(&self, ages: Option<&TrailAges>, index: usize) -> PixelDecay {
+        let (Some(duration_ms), Some(ages)) = (self.duration_ms, ages) else {
+            return PixelDecay::Step(if self.is_active() { self.step } else { 0.0 });
+        };
+        if self.curve == DecayCurve::Linear {
+            return PixelDecay::Step(self.step);
+        }
+        let Some(age_ms) = ages.age_ms(index, self.timestamp_ms) else {
+            return PixelDecay::Retain(0.0);
+        };
+
+        // Scale the remaining distance by how much strength the curve lost since the last pass
+        let before = self.curve.strength(age_ms - self.elapsed_ms, duration_ms);
+        if before <= 0.0 {
+            return PixelDecay::Retain(0.0);
+        }
+        let now = self.curve.strength(age_ms, duration_ms);
+        PixelDecay::Retain((now / before).clamp(0.0, 1.0))
+    }
+}
+
+/// Converts a duration in milliseconds to the amount the linear curve fades over `elapsed_ms`.
+fn linear_step(duration_ms: Option<u32>, elapsed_ms: f64) -> f32 {
+    match duration_ms {
+        None => 0.0, // No decay
+        Some(0) => 255.0, // Instant decay
+        Some(ms) => (255.0 * elapsed_ms / ms as f64) as f32,
+    }
+}
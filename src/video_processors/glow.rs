@@ -0,0 +1,88 @@
+use image::{
+    Rgba,
+    Rgba32FImage,
+    RgbaImage,
+    imageops::fast_blur,
+};
+use serde::Deserialize;
+
+/// Configuration for the glow post-process, which gives trails a neon light-trail look.
+/// Applies to the `Colored`, `Stable` and `Priority` effects.
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GlowSettings {
+    /// If `true`, the trails are blurred and added back onto the frame.
+    pub enabled: bool,
+    /// The blur radii in pixels. Each radius adds one glow layer, mixing small and large radii
+    /// gives a tight core with a wide halo.
+    pub radii: Vec<f32>,
+    /// The strength of each glow layer. `1.0` adds the blurred trails at full brightness.
+    pub intensity: f32,
+    /// Only trail pixels brighter than this luminance glow (0.0 to 255.0).
+    pub threshold: f32,
+}
+
+impl Default for GlowSettings {
+    fn default() -> Self {
+        GlowSettings {
+            enabled: false,
+            radii: vec![4.0, 16.0],
+            intensity: 0.6,
+            threshold: 32.0,
+        }
+    }
+}
+
+/// Blurs the bright parts of the `trails` layer at every configured radius and adds the result
+/// onto `output_frame`. The layer uses the 0.0 to 255.0 scale of the effect canvases.
+pub fn apply_glow(output_frame: &mut RgbaImage, trails: &Rgba32FImage, glow: &GlowSettings) {
+    if !glow.enabled || glow.radii.is_empty() || glow.intensity <= 0.0 {
+        return;
+    }
+
+    // Keep only the pixels above the threshold, normalized since the blur clamps floats to 1.0
+    let (width, height) = trails.dimensions();
+    let bright = Rgba32FImage::from_fn(width, height, |x, y| {
+        let pixel = trails.get_pixel(x, y);
+        let luminance = 0.2126 * pixel[0] + 0.7152 * pixel[1] + 0.0722 * pixel[2];
+        if luminance < glow.threshold {
+            Rgba([0.0, 0.0, 0.0, 0.0])
+        } else {
+            Rgba([pixel[0] / 255.0, pixel[1] / 255.0, pixel[2] / 255.0, 1.0])
+        }
+    });
+
+    let mut glow_sum = vec![[0.0_f32; 3]; (width * height) as usize];
+    for &radius in &glow.radii {
+        let blurred = if radius > 0.0 { fast_blur(&bright, radius) } else { bright.clone() };
+        for (sum, pixel) in glow_sum.iter_mut().zip(blurred.pixels()) {
+            sum[0] += pixel[0];
+            sum[1] += pixel[1];
+            sum[2] += pixel[2];
+        }
+    }
+
+    for (output_pixel, sum) in output_frame.pixels_mut().zip(&glow_sum) {
+        for channel in 0..3 {
+            let added = sum[channel] * glow.intensity * 255.0;
+            output_pixel[channel] = (output_pixel[channel] as f32 + added).round().min(255.0) as u8;
+        }
+    }
+}
+
+/// Extracts the trail regions of an output frame as a glow layer: pixels differing from the live
+/// `current_frame` by more than `threshold`. Unlike `Stable`, which finds its trails on the
+/// canvas, the moving object itself glows too.
+pub fn frame_glow_layer(output_frame: &RgbaImage, current_frame: &RgbaImage, threshold: i16) -> Rgba32FImage {
+    let (width, height) = output_frame.dimensions();
+    Rgba32FImage::from_fn(width, height, |x, y| {
+        let output_pixel = output_frame.get_pixel(x, y);
+        let current_pixel = current_frame.get_pixel(x, y);
+        let is_trail = (0..3).any(|c| (output_pixel[c] as i16 - current_pixel[c] as i16).abs() > threshold);
+        if is_trail {
+            Rgba(output_pixel.0.map(f32::from))
+        } else {
+            Rgba([0.0, 0.0, 0.0, 0.0])
+        }
+    })
+}
@@ -0,0 +1,206 @@
+use image::Rgba;
+use serde::{
+    Deserialize,
+    Deserializer,
+    de,
+};
+use std::{
+    error::Error,
+    fs,
+    path::{
+        Path,
+        PathBuf,
+    },
+};
+
+/// Defines what drives the position along a trail gradient.
+#[allow(dead_code)]
+#[derive(Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GradientIndex {
+    /// New trails take the start of the gradient and slide towards the end as they fade.
+    Age,
+    /// The whole trail cycles through the gradient over time, like `rainbow_mode`.
+    Time,
+    /// Each trail pixel is colored by the luminance of the pixel that caused it.
+    Luminance,
+}
+
+/// A single color stop of a `Gradient`.
+#[derive(Clone, Copy)]
+pub struct GradientStop {
+    /// The position of the stop along the gradient (0.0 to 1.0).
+    pub position: f32,
+    pub color: Rgba<u8>,
+}
+
+/// A multi-stop color gradient, or a palette of discrete colors.
+#[derive(Clone, Deserialize)]
+#[serde(try_from = "GradientConfig")]
+pub struct Gradient {
+    stops: Vec<GradientStop>,
+    /// If `true`, colors are interpolated between stops, otherwise the nearest stop is used.
+    pub interpolate: bool,
+}
+
+impl Gradient {
+    /// Creates a gradient from a list of stops, which don't need to be sorted.
+    pub fn new(mut stops: Vec<GradientStop>, interpolate: bool) -> Self {
+        stops.sort_by(|a, b| a.position.total_cmp(&b.position));
+        Gradient { stops, interpolate }
+    }
+
+    /// Creates a gradient with the given colors spread evenly from 0.0 to 1.0.
+    pub fn evenly_spaced(colors: &[Rgba<u8>], interpolate: bool) -> Self {
+        let last = colors.len().saturating_sub(1).max(1) as f32;
+        let stops = colors
+            .iter()
+            .enumerate()
+            .map(|(i, &color)| GradientStop { position: i as f32 / last, color })
+            .collect();
+        Gradient::new(stops, interpolate)
+    }
+
+    /// Loads a gradient from a palette file.
+    ///
+    /// Each non-empty line holds one color, either as `#rrggbb` or as three `r g b` values,
+    /// optionally preceded by a position between 0.0 and 1.0. If no line has a position, the
+    /// colors are spread evenly. Lines starting with `#` followed by a space are comments.
+    pub fn from_file(path: &Path, interpolate: bool) -> Result<Self, Box<dyn Error>> {
+        let contents = fs::read_to_string(path)?;
+        let mut positions = Vec::new();
+        let mut colors = Vec::new();
+
+        for (line_number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with("# ") || line == "#" {
+                continue;
+            }
+            let (position, color) = parse_palette_line(line)
+                .ok_or_else(|| format!("{}:{}: invalid palette entry '{}'", path.display(), line_number + 1, line))?;
+            positions.push(position);
+            colors.push(color);
+        }
+
+        if colors.is_empty() {
+            return Err(format!("{}: palette has no colors", path.display()).into());
+        }
+
+        if positions.iter().all(Option::is_none) {
+            Ok(Gradient::evenly_spaced(&colors, interpolate))
+        } else if positions.iter().all(Option::is_some) {
+            let stops = positions
+                .into_iter()
+                .zip(colors)
+                .map(|(position, color)| GradientStop { position: position.unwrap(), color })
+                .collect();
+            Ok(Gradient::new(stops, interpolate))
+        } else {
+            Err(format!("{}: either all palette entries need a position or none", path.display()).into())
+        }
+    }
+
+    /// Samples the gradient at `t` (0.0 to 1.0). A NaN `t`, such as from a NaN setting, samples the
+    /// start of the gradient.
+    pub fn sample(&self, t: f32) -> Rgba<u8> {
+        let t = if t.is_nan() { 0.0 } else { t.clamp(0.0, 1.0) };
+        let Some(first) = self.stops.first() else {
+            return Rgba([0, 0, 0, 255]);
+        };
+        let last = self.stops[self.stops.len() - 1];
+        if t <= first.position {
+            return first.color;
+        }
+        if t >= last.position {
+            return last.color;
+        }
+
+        // Find the pair of stops surrounding `t`
+        let upper_index = self.stops.iter().position(|stop| stop.position >= t).unwrap();
+        let lower = self.stops[upper_index - 1];
+        let upper = self.stops[upper_index];
+        let span = upper.position - lower.position;
+        let local_t = if span > 0.0 { (t - lower.position) / span } else { 1.0 };
+
+        if !self.interpolate {
+            return if local_t < 0.5 { lower.color } else { upper.color };
+        }
+
+        Rgba([
+            (lower.color[0] as f32 + (upper.color[0] as f32 - lower.color[0] as f32) * local_t) as u8,
+            (lower.color[1] as f32 + (upper.color[1] as f32 - lower.color[1] as f32) * local_t) as u8,
+            (lower.color[2] as f32 + (upper.color[2] as f32 - lower.color[2] as f32) * local_t) as u8,
+            255,
+        ])
+    }
+}
+
+/// A gradient as written in the config file, either loaded from a palette file or listed inline.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct GradientConfig {
+    /// The path of a palette file.
+    palette: Option<PathBuf>,
+    /// Colors as `#rrggbb`, spread evenly over the gradient.
+    colors: Option<Vec<String>>,
+    #[serde(default = "default_interpolate")]
+    interpolate: bool,
+}
+
+fn default_interpolate() -> bool {
+    true
+}
+
+impl TryFrom<GradientConfig> for Gradient {
+    type Error = String;
+
+    fn try_from(config: GradientConfig) -> Result<Self, Self::Error> {
+        match (config.palette, config.colors) {
+            (Some(path), None) => Gradient::from_file(&path, config.interpolate).map_err(|e| e.to_string()),
+            (None, Some(colors)) if !colors.is_empty() => {
+                let colors = colors
+                    .iter()
+                    .map(|hex| parse_hex_color(hex).ok_or_else(|| format!("invalid color '{}'", hex)))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(Gradient::evenly_spaced(&colors, config.interpolate))
+            }
+            _ => Err("a gradient needs either a `palette` file or a non-empty list of `colors`".to_string()),
+        }
+    }
+}
+
+/// Deserializes a color written as `#rrggbb`.
+pub fn deserialize_hex_color<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Rgba<u8>, D::Error> {
+    let hex = String::deserialize(deserializer)?;
+    parse_hex_color(&hex).ok_or_else(|| de::Error::custom(format!("invalid color '{}'", hex)))
+}
+
+/// Parses a hex color in the form `#rrggbb` or `rrggbb`.
+pub fn parse_hex_color(hex: &str) -> Option<Rgba<u8>> {
+    let hex = hex.strip_prefix('#').unwrap_or(hex);
+    if hex.len() != 6 || !hex.is_ascii() {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+    Some(Rgba([channel(0)?, channel(2)?, channel(4)?, 255]))
+}
+
+/// Parses a single palette line into an optional position and a color.
+fn parse_palette_line(line: &str) -> Option<(Option<f32>, Rgba<u8>)> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    match fields.as_slice() {
+        [hex] => Some((None, parse_hex_color(hex)?)),
+        [position, hex] => Some((Some(parse_position(position)?), parse_hex_color(hex)?)),
+        [r, g, b] => Some((None, Rgba([r.parse().ok()?, g.parse().ok()?, b.parse().ok()?, 255]))),
+        [position, r, g, b] => Some((
+            Some(parse_position(position)?),
+            Rgba([r.parse().ok()?, g.parse().ok()?, b.parse().ok()?, 255]),
+        )),
+        _ => None,
+    }
+}
+
+/// Parses a gradient stop position, which must be between 0.0 and 1.0.
+fn parse_position(position: &str) -> Option<f32> {
+    position.parse::<f32>().ok().filter(|p| (0.0..=1.0).contains(p))
+}
@@ -0,0 +1,201 @@
+use serde::Deserialize;
+
+use crate::video_processors::{
+    EffectSettings,
+    colored::HueMode,
+    decay::DecayCurve,
+};
+
+/// Defines how a keyframe track moves from one keyframe to the next.
+#[derive(Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Interpolation {
+    /// Moves at a constant rate.
+    #[default]
+    Linear,
+    /// Holds the value until the next keyframe.
+    Step,
+    /// Eases along a cubic bezier curve with control points `[x1, y1, x2, y2]`, as in CSS
+    /// `cubic-bezier()`.
+    Bezier([f32; 4]),
+}
+
+/// A value of a settings field at a point in time.
+#[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Keyframe {
+    /// The time of the keyframe, in milliseconds from the start of the video.
+    pub time_ms: f64,
+    pub value: f32,
+    /// How the value moves from this keyframe to the next one.
+    #[serde(default)]
+    pub interpolation: Interpolation,
+}
+
+/// Animates one numeric field of the `EffectSettings` over time.
+#[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KeyframeTrack {
+    /// The path of the animated field, for example `colored.rainbow_speed`.
+    pub field: String,
+    pub keyframes: Vec<Keyframe>,
+}
+
+impl KeyframeTrack {
+    /// Evaluates the track at `time_ms`. Before the first and after the last keyframe, the value
+    /// of the nearest keyframe is held.
+    pub fn value_at(&self, time_ms: f64) -> Option<f32> {
+        let first = self.keyframes.first()?;
+        if time_ms <= first.time_ms {
+            return Some(first.value);
+        }
+        let next_index = self.keyframes.iter().position(|key| key.time_ms > time_ms);
+        let Some(next_index) = next_index else {
+            return self.keyframes.last().map(|key| key.value);
+        };
+        let key = &self.keyframes[next_index - 1];
+        let next = &self.keyframes[next_index];
+
+        let t = ((time_ms - key.time_ms) / (next.time_ms - key.time_ms)) as f32;
+        let eased = match key.interpolation {
+            Interpolation::Linear => t,
+            Interpolation::Step => 0.0,
+            Interpolation::Bezier([x1, y1, x2, y2]) => cubic_bezier(t, x1, y1, x2, y2),
+        };
+        Some(key.value + (next.value - key.value) * eased)
+    }
+}
+
+/// A numeric field of the `EffectSettings` that can be driven by keyframes or the audio.
+pub enum NumericField<'a> {
+    Float(&'a mut f32),
+    Count(&'a mut usize),
+    /// An optional duration in milliseconds, negative values clear it.
+    Duration(&'a mut Option<u32>),
+    /// A duration in milliseconds, negative values read as zero.
+    Millis(&'a mut u32),
+    /// A color channel (0 to 255).
+    Channel(&'a mut u8),
+}
+
+impl NumericField<'_> {
+    /// Reads the value of the field, `None` durations read as -1.0.
+    pub fn get(&self) -> f32 {
+        match self {
+            NumericField::Float(field) => **field,
+            NumericField::Count(field) => **field as f32,
+            NumericField::Duration(field) => field.map_or(-1.0, |ms| ms as f32),
+            NumericField::Millis(field) => **field as f32,
+            NumericField::Channel(field) => **field as f32,
+        }
+    }
+
+    /// Writes a value into the field, rounding and clamping it for integer fields.
+    pub fn set(self, value: f32) {
+        match self {
+            NumericField::Float(field) => *field = value,
+            NumericField::Count(field) => *field = value.round().max(1.0) as usize,
+            NumericField::Duration(field) => *field = (value >= 0.0).then(|| value.round() as u32),
+            NumericField::Millis(field) => *field = value.round().max(0.0) as u32,
+            NumericField::Channel(field) => *field = value.round().clamp(0.0, 255.0) as u8,
+        }
+    }
+}
+
+impl EffectSettings {
+    /// Looks up a numeric field by its path, for example `blended.blend_factor`. The fields of a
+    /// decay curve or hue mode are only found if it is the configured variant.
+    pub fn numeric_field_mut(&mut self, path: &str) -> Option<NumericField<'_>> {
+        if let Some(index) = path.strip_prefix("glow.radii.") {
+            return self.glow.radii.get_mut(index.parse::<usize>().ok()?).map(NumericField::Float);
+        }
+        if let Some(name) = path.strip_prefix("colored.hue_mode.") {
+            return hue_mode_field(&mut self.colored.hue_mode, name);
+        }
+        if let Some((effect, name)) = path.split_once(".decay_curve.") {
+            let curve = match effect {
+                "stable" => &mut self.stable.decay_curve,
+                "blended" => &mut self.blended.decay_curve,
+                "colored" => &mut self.colored.decay_curve,
+                "priority" => &mut self.priority.decay_curve,
+                _ => return None,
+            };
+            return decay_curve_field(curve, name);
+        }
+
+        let field = match path {
+            "motion_threshold_percent" => NumericField::Float(&mut self.motion_threshold_percent),
+            "n_frames_step" => NumericField::Count(&mut self.n_frames_step),
+            "max_trail_age_ms" => NumericField::Duration(&mut self.max_trail_age_ms),
+            "glow.intensity" => NumericField::Float(&mut self.glow.intensity),
+            "glow.threshold" => NumericField::Float(&mut self.glow.threshold),
+            "stable.burn_in_factor" => NumericField::Float(&mut self.stable.burn_in_factor),
+            "stable.tracer_duration_ms" => NumericField::Duration(&mut self.stable.tracer_duration_ms),
+            "blended.blend_factor" => NumericField::Float(&mut self.blended.blend_factor),
+            "blended.tracer_duration_ms" => NumericField::Duration(&mut self.blended.tracer_duration_ms),
+            "colored.rainbow_speed" => NumericField::Float(&mut self.colored.rainbow_speed),
+            "colored.tracer_opacity" => NumericField::Float(&mut self.colored.tracer_opacity),
+            "colored.color.r" => NumericField::Channel(&mut self.colored.color.0[0]),
+            "colored.color.g" => NumericField::Channel(&mut self.colored.color.0[1]),
+            "colored.color.b" => NumericField::Channel(&mut self.colored.color.0[2]),
+            "colored.tracer_duration_ms" => NumericField::Duration(&mut self.colored.tracer_duration_ms),
+            "priority.tracer_duration_ms" => NumericField::Duration(&mut self.priority.tracer_duration_ms),
+            _ => return None,
+        };
+        Some(field)
+    }
+
+    /// Returns a copy of the settings with every keyframe track evaluated at `time_ms`.
+    pub fn at_time(&self, tracks: &[KeyframeTrack], time_ms: f64) -> EffectSettings {
+        let mut settings = self.clone();
+        for track in tracks {
+            if let (Some(value), Some(field)) = (track.value_at(time_ms), settings.numeric_field_mut(&track.field)) {
+                field.set(value);
+            }
+        }
+        settings
+    }
+}
+
+/// Looks up the field `name` of a hue mode, for example `spread`.
+fn hue_mode_field<'a>(hue_mode: &'a mut HueMode, name: &str) -> Option<NumericField<'a>> {
+    let field = match (hue_mode, name) {
+        (HueMode::Spatial { spread, .. } | HueMode::Motion { spread }, "spread") => spread,
+        (HueMode::Spatial { angle, .. }, "angle") => angle,
+        (HueMode::PingPong { from, .. }, "from") => from,
+        (HueMode::PingPong { to, .. }, "to") => to,
+        _ => return None,
+    };
+    Some(NumericField::Float(field))
+}
+
+/// Looks up the field `name` of a decay curve, for example `half_life_ms`.
+fn decay_curve_field<'a>(curve: &'a mut DecayCurve, name: &str) -> Option<NumericField<'a>> {
+    match (curve, name) {
+        (DecayCurve::Exponential { half_life_ms }, "half_life_ms") => Some(NumericField::Millis(half_life_ms)),
+        (DecayCurve::HoldThenFade { hold_fraction }, "hold_fraction") => Some(NumericField::Float(hold_fraction)),
+        _ => None,
+    }
+}
+
+/// Evaluates a CSS-style cubic bezier easing curve at `x`, with the end points fixed at (0, 0)
+/// and (1, 1).
+fn cubic_bezier(x: f32, x1: f32, y1: f32, x2: f32, y2: f32) -> f32 {
+    let bezier = |t: f32, p1: f32, p2: f32| {
+        let u = 1.0 - t;
+        3.0 * u * u * t * p1 + 3.0 * u * t * t * p2 + t * t * t
+    };
+
+    // The curve is monotonic in x for control points within 0..1, so bisect for the t matching x
+    let (x1, x2) = (x1.clamp(0.0, 1.0), x2.clamp(0.0, 1.0));
+    let (mut low, mut high) = (0.0_f32, 1.0_f32);
+    for _ in 0..32 {
+        let mid = (low + high) / 2.0;
+        if bezier(mid, x1, x2) < x {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+    bezier((low + high) / 2.0, y1, y2)
+}
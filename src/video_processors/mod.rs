@@ -1,19 +1,53 @@
+use image::{
+    Rgba32FImage,
+    RgbaImage,
+};
+use serde::Deserialize;
+
 use crate::video_processors::{
-    blended::BlendedSettings,
-    colored::ColoredSettings,
-    priority::PrioritySettings,
-    stable::StableSettings,
+    blended::{
+        BlendedSettings,
+        process_blended_frame,
+    },
+    colored::{
+        ColoredSettings,
+        process_colored_frame,
+    },
+    glow::GlowSettings,
+    priority::{
+        PrioritySettings,
+        process_priority_frame,
+    },
+    stable::{
+        StableSettings,
+        process_stable_frame,
+    },
+    decay::DecayCurve,
+    trail_age::TrailAges,
+    utils::{
+        MotionMask,
+        to_float_canvas,
+    },
 };
 
+pub mod audio;
 pub mod blended;
 pub mod colored;
+pub mod decay;
+pub mod glow;
+pub mod gradient;
+pub mod keyframes;
 pub mod priority;
+pub mod segments;
 pub mod stable;
+pub mod stack;
+pub mod trail_age;
 pub mod utils;
 
 /// Defines the visual effects.
 #[allow(dead_code)]
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum EffectMode {
     /// A stable trail effect that burns the motion into the frame.
     Stable,
@@ -26,6 +60,8 @@ pub enum EffectMode {
 }
 
 /// Holds all the settings.
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct EffectSettings {
     pub mode: EffectMode,
     /// If `true`, the audio from the input video will be copied to the output.
@@ -37,6 +73,14 @@ pub struct EffectSettings {
     pub use_edge_correction: bool,
     /// The number of frames to skip between trail updates. 1 applies the effect on every frame.
     pub n_frames_step: usize,
+    /// If `true`, the time at which each pixel last received a trail is recorded alongside the
+    /// canvas, so effects can use the exact age of a trail.
+    pub track_trail_age: bool,
+    /// Trails older than this, in milliseconds, are discarded regardless of how far they faded.
+    /// Setting this enables trail age tracking.
+    pub max_trail_age_ms: Option<u32>,
+    /// The glow post-process applied to the trails.
+    pub glow: GlowSettings,
 
     pub stable: StableSettings,
     pub blended: BlendedSettings,
@@ -44,10 +88,99 @@ pub struct EffectSettings {
     pub priority: PrioritySettings,
 }
 
-/// A helper struct to hold the calculated per-frame decay amounts for each effect.
-pub struct CalculatedDecay {
-    pub stable: f32,
-    pub blended: f32,
-    pub colored: f32,
-    pub priority: f32,
+impl Default for EffectSettings {
+    fn default() -> Self {
+        EffectSettings {
+            // General
+            mode: EffectMode::Priority,
+            preserve_audio: true,
+            motion_threshold_percent: 0.1,
+            use_edge_correction: false,
+            n_frames_step: 1,
+            track_trail_age: false,
+            max_trail_age_ms: None,
+            glow: GlowSettings::default(),
+
+            // Mode-Specific
+            stable: StableSettings::default(),
+            blended: BlendedSettings::default(),
+            colored: ColoredSettings::default(),
+            priority: PrioritySettings::default(),
+        }
+    }
+}
+
+impl EffectSettings {
+    /// Returns `true` if the per-pixel trail ages need to be recorded.
+    /// Non-linear decay curves need the exact age of each trail.
+    pub fn tracks_trail_age(&self) -> bool {
+        let curves = [
+            self.stable.decay_curve,
+            self.blended.decay_curve,
+            self.colored.decay_curve,
+            self.priority.decay_curve,
+        ];
+        self.track_trail_age
+            || self.max_trail_age_ms.is_some()
+            || curves.iter().any(|curve| *curve != DecayCurve::Linear)
+    }
+}
+
+/// Holds the state an effect carries from one frame to the next.
+#[derive(Clone)]
+pub struct EffectState {
+    /// The persistent canvas the trails are drawn on, kept in floating-point precision on the
+    /// 0.0 to 255.0 scale and only rounded to 8 bits for output.
+    pub canvas: Rgba32FImage,
+    /// How far the rainbow has cycled, in degrees of hue. Grows without wrapping so ping-pong
+    /// cycles stay continuous, in double precision so the steps stay even in renders of many
+    /// hours.
+    pub rainbow_hue: f64,
+    /// When each canvas pixel last received a trail, if trail age tracking is enabled.
+    pub trail_ages: Option<TrailAges>,
+    /// The timestamp of the last decay pass over the canvas, in milliseconds.
+    pub last_decay_ms: Option<f64>,
+}
+
+impl EffectState {
+    /// Creates the initial state from the first frame of the video, shown at `timestamp_ms`.
+    pub fn new(first_frame: &RgbaImage, settings: &EffectSettings, timestamp_ms: f64) -> Self {
+        let (width, height) = first_frame.dimensions();
+        EffectState {
+            canvas: if settings.mode == EffectMode::Colored {
+                Rgba32FImage::new(width, height)
+            } else {
+                to_float_canvas(first_frame)
+            },
+            rainbow_hue: 0.0,
+            trail_ages: settings.tracks_trail_age().then(|| TrailAges::new(width, height)),
+            last_decay_ms: Some(timestamp_ms),
+        }
+    }
+}
+
+/// Processes a single frame with the effect selected by `settings.mode`, using the `motion_mask`
+/// detected on the source frames.
+pub fn process_frame(
+    state: &mut EffectState,
+    current_frame: &RgbaImage,
+    motion_mask: &MotionMask,
+    settings: &EffectSettings,
+    should_update_canvas: bool,
+    timestamp_ms: f64,
+) -> RgbaImage {
+    match settings.mode {
+        EffectMode::Stable => {
+            process_stable_frame(state, current_frame, motion_mask, settings, should_update_canvas, timestamp_ms)
+        }
+        EffectMode::Blended => {
+            process_blended_frame(state, current_frame, motion_mask, settings, should_update_canvas, timestamp_ms)
+        }
+        EffectMode::Colored => {
+            process_colored_frame(state, current_frame, motion_mask, settings, should_update_canvas, timestamp_ms)
+        }
+        EffectMode::Priority => {
+            process_priority_frame(state, current_frame, settings, should_update_canvas, timestamp_ms)
+        }
+    }
 }
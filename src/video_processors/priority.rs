@@ -1,14 +1,27 @@
 use image::RgbaImage;
+use serde::Deserialize;
 
 use crate::video_processors::{
-    CalculatedDecay,
     EffectSettings,
-    utils::move_towards,
+    EffectState,
+    decay::{
+        DecayCurve,
+        DecayPass,
+    },
+    glow::{
+        apply_glow,
+        frame_glow_layer,
+    },
+    utils::{
+        BlendMode,
+        blend_pixel,
+    },
 };
 
 /// Defines the comparison logic for the `Priority` effect, need to allow dead code to remain P R E T T Y
 #[allow(dead_code)]
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum PriorityMode {
     /// Keeps the brighter of the two pixels
     Lightest,
@@ -17,55 +30,99 @@ pub enum PriorityMode {
 }
 
 /// Configuration for the `Priority` effect.
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct PrioritySettings {
     pub mode: PriorityMode,
     /// The duration a trail should last, in milliseconds, none for permanent.
     pub tracer_duration_ms: Option<u32>,
+    /// The shape of the fade over `tracer_duration_ms`.
+    pub decay_curve: DecayCurve,
+    /// How the priority canvas is composited onto the frame.
+    pub blend_mode: BlendMode,
+}
+
+impl Default for PrioritySettings {
+    fn default() -> Self {
+        PrioritySettings {
+            mode: PriorityMode::Lightest,
+            tracer_duration_ms: Some(5000),
+            decay_curve: DecayCurve::Linear,
+            blend_mode: BlendMode::Normal,
+        }
+    }
 }
 
 /// Processes a single frame for the `Priority` effect.
 /// This effect creates trails by comparing the brightness of the canvas pixel and the current
 /// frame pixel, keeping either the lightest or the darkest of the two.
 pub fn process_priority_frame(
-    canvas: &mut RgbaImage,
+    state: &mut EffectState,
     current_frame: &RgbaImage,
     settings: &EffectSettings,
-    decay: &CalculatedDecay,
     should_update_canvas: bool,
+    timestamp_ms: f64,
 ) -> RgbaImage {
+    let canvas = &mut state.canvas;
+    let (width, _height) = canvas.dimensions();
     if should_update_canvas {
-        let decay_amount = decay.priority;
+        let decay_pass = DecayPass::new(
+            settings.priority.decay_curve,
+            settings.priority.tracer_duration_ms,
+            timestamp_ms,
+            state.last_decay_ms,
+        );
         for (x, y, canvas_pixel) in canvas.enumerate_pixels_mut() {
+            let index = (y * width + x) as usize;
             let current_pixel = *current_frame.get_pixel(x, y);
 
+            // Discard trails that outlived the maximum age
+            if let Some(ages) = state.trail_ages.as_mut()
+                && ages.is_expired(index, timestamp_ms, settings.max_trail_age_ms)
+            {
+                *canvas_pixel = image::Rgba(current_pixel.0.map(f32::from));
+                ages.clear(index);
+            }
+
             // Decay the canvas pixel towards the current frame pixel
-            if decay_amount > 0.0 {
+            if decay_pass.is_active() {
+                let pixel_decay = decay_pass.for_pixel(state.trail_ages.as_ref(), index);
                 *canvas_pixel = image::Rgba([
-                    move_towards(canvas_pixel[0], current_pixel[0], decay_amount),
-                    move_towards(canvas_pixel[1], current_pixel[1], decay_amount),
-                    move_towards(canvas_pixel[2], current_pixel[2], decay_amount),
-                    255,
+                    pixel_decay.apply(canvas_pixel[0], current_pixel[0] as f32),
+                    pixel_decay.apply(canvas_pixel[1], current_pixel[1] as f32),
+                    pixel_decay.apply(canvas_pixel[2], current_pixel[2] as f32),
+                    255.0,
                 ]);
             }
 
             // Apply the priority logic
-            let canvas_brightness = canvas_pixel[0] as u16 + canvas_pixel[1] as u16 + canvas_pixel[2] as u16;
-            let current_brightness = current_pixel[0] as u16 + current_pixel[1] as u16 + current_pixel[2] as u16;
+            let canvas_brightness = canvas_pixel[0] + canvas_pixel[1] + canvas_pixel[2];
+            let current_brightness = current_pixel[0] as f32 + current_pixel[1] as f32 + current_pixel[2] as f32;
 
-            match settings.priority.mode {
-                PriorityMode::Lightest => {
-                    if current_brightness > canvas_brightness {
-                        *canvas_pixel = current_pixel;
-                    }
-                }
-                PriorityMode::Darkest => {
-                    if current_brightness < canvas_brightness {
-                        *canvas_pixel = current_pixel;
-                    }
+            let takes_priority = match settings.priority.mode {
+                PriorityMode::Lightest => current_brightness > canvas_brightness,
+                PriorityMode::Darkest => current_brightness < canvas_brightness,
+            };
+            if takes_priority {
+                *canvas_pixel = image::Rgba(current_pixel.0.map(f32::from));
+                if let Some(ages) = state.trail_ages.as_mut() {
+                    ages.stamp(index, timestamp_ms);
                 }
             }
         }
+        state.last_decay_ms = Some(timestamp_ms);
+    }
+
+    let mut output_frame = current_frame.clone();
+    for (x, y, output_pixel) in output_frame.enumerate_pixels_mut() {
+        *output_pixel = blend_pixel(output_pixel, canvas.get_pixel(x, y), settings.priority.blend_mode, 1.0);
+    }
+
+    if settings.glow.enabled {
+        let motion_thresh = (255.0 * settings.motion_threshold_percent) as i16;
+        let trails = frame_glow_layer(&output_frame, current_frame, motion_thresh);
+        apply_glow(&mut output_frame, &trails, &settings.glow);
     }
 
-    canvas.clone()
+    output_frame
 }
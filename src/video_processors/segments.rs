@@ -0,0 +1,64 @@
+use image::RgbaImage;
+use serde::Deserialize;
+
+use crate::video_processors::EffectMode;
+
+/// A time range of the video the effect is applied to.
+#[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Segment {
+    /// The start of the range, in milliseconds from the start of the video.
+    pub start_ms: f64,
+    /// The end of the range in milliseconds, none to run until the end of the video.
+    pub end_ms: Option<f64>,
+    /// How long the effect takes to fade in at the start of the range, in milliseconds.
+    #[serde(default)]
+    pub fade_in_ms: f64,
+    /// How long the effect takes to fade out at the end of the range, in milliseconds.
+    #[serde(default)]
+    pub fade_out_ms: f64,
+    /// The effect used in this range, none for the `mode` of the effect settings.
+    pub mode: Option<EffectMode>,
+}
+
+impl Segment {
+    /// Returns `true` if `time_ms` lies within the range.
+    pub fn contains(&self, time_ms: f64) -> bool {
+        time_ms >= self.start_ms && self.end_ms.is_none_or(|end| time_ms < end)
+    }
+
+    /// Returns how strongly the effect is applied at `time_ms` (0.0 to 1.0), following the fades at
+    /// both ends of the range.
+    pub fn weight_at(&self, time_ms: f64) -> f32 {
+        let fade_in = if self.fade_in_ms > 0.0 {
+            ((time_ms - self.start_ms) / self.fade_in_ms).clamp(0.0, 1.0)
+        } else {
+            1.0
+        };
+        let fade_out = match self.end_ms {
+            Some(end) if self.fade_out_ms > 0.0 => ((end - time_ms) / self.fade_out_ms).clamp(0.0, 1.0),
+            _ => 1.0,
+        };
+        fade_in.min(fade_out) as f32
+    }
+}
+
+/// Finds the segment active at `time_ms`, returning its index.
+pub fn active_segment(segments: &[Segment], time_ms: f64) -> Option<usize> {
+    segments.iter().position(|segment| segment.contains(time_ms))
+}
+
+/// Mixes the `processed` frame over the `source` frame by `weight` (0.0 to 1.0).
+pub fn mix_frames(source: &RgbaImage, mut processed: RgbaImage, weight: f32) -> RgbaImage {
+    if weight >= 1.0 {
+        return processed;
+    }
+    for (processed_pixel, source_pixel) in processed.pixels_mut().zip(source.pixels()) {
+        for channel in 0..3 {
+            let source_c = source_pixel[channel] as f32;
+            let processed_c = processed_pixel[channel] as f32;
+            processed_pixel[channel] = (source_c + (processed_c - source_c) * weight).round() as u8;
+        }
+    }
+    processed
+}
@@ -1,16 +1,29 @@
-use image::RgbaImage;
+use image::{
+    Rgba,
+    Rgba32FImage,
+    RgbaImage,
+};
+use serde::Deserialize;
 
 use crate::video_processors::{
-    CalculatedDecay,
     EffectSettings,
+    EffectState,
+    decay::{
+        DecayCurve,
+        DecayPass,
+    },
+    glow::apply_glow,
+    trail_age::TrailAges,
     utils::{
+        BlendMode,
+        MotionMask,
         apply_compositing_and_correction,
-        create_motion_mask,
-        move_towards,
     },
 };
 
 /// Configuration for the `Stable` effect.
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct StableSettings {
     /// The opacity of new trails when they are stamped onto the canvas (0.0 to 1.0).
     /// - `0.0` makes the trail completely transparent.
@@ -18,55 +31,121 @@ pub struct StableSettings {
     pub burn_in_factor: f32,
     /// The duration a trail should last, in milliseconds, none for permanent.
     pub tracer_duration_ms: Option<u32>,
+    /// The shape of the fade over `tracer_duration_ms`.
+    pub decay_curve: DecayCurve,
+    /// How the burned-in trails are composited onto the frame.
+    pub blend_mode: BlendMode,
+}
+
+impl Default for StableSettings {
+    fn default() -> Self {
+        StableSettings {
+            burn_in_factor: 1.0,
+            tracer_duration_ms: None,
+            decay_curve: DecayCurve::Linear,
+            blend_mode: BlendMode::Normal,
+        }
+    }
 }
 
 /// Processes a single frame for the `Stable` effect.
 /// This effect creates a stable trail by "burning in" motion into a persistent canvas.
 /// The canvas gradually fades back to the current frame in static areas.
 pub fn process_stable_frame(
-    canvas: &mut RgbaImage,
+    state: &mut EffectState,
     current_frame: &RgbaImage,
-    prev_frame: &RgbaImage,
+    motion_mask: &MotionMask,
     settings: &EffectSettings,
-    decay: &CalculatedDecay,
     should_update_canvas: bool,
+    timestamp_ms: f64,
 ) -> RgbaImage {
     let motion_thresh = (255.0 * settings.motion_threshold_percent) as i16;
+    let canvas = &mut state.canvas;
     let (width, _height) = canvas.dimensions();
-    let motion_mask = create_motion_mask(current_frame, prev_frame, motion_thresh);
 
     if should_update_canvas {
-        let decay_amount = decay.stable;
+        let decay_pass = DecayPass::new(
+            settings.stable.decay_curve,
+            settings.stable.tracer_duration_ms,
+            timestamp_ms,
+            state.last_decay_ms,
+        );
         for (x, y, canvas_pixel) in canvas.enumerate_pixels_mut() {
-            let is_in_motion = motion_mask[(y * width + x) as usize];
+            let index = (y * width + x) as usize;
+            let is_in_motion = motion_mask.is_moving(index);
             if is_in_motion {
                 let current_pixel = *current_frame.get_pixel(x, y);
                 // Blend for burn-in
                 let factor = settings.stable.burn_in_factor;
                 *canvas_pixel = image::Rgba([
-                    (canvas_pixel[0] as f32 * (1.0 - factor) + current_pixel[0] as f32 * factor) as u8,
-                    (canvas_pixel[1] as f32 * (1.0 - factor) + current_pixel[1] as f32 * factor) as u8,
-                    (canvas_pixel[2] as f32 * (1.0 - factor) + current_pixel[2] as f32 * factor) as u8,
-                    255,
+                    canvas_pixel[0] * (1.0 - factor) + current_pixel[0] as f32 * factor,
+                    canvas_pixel[1] * (1.0 - factor) + current_pixel[1] as f32 * factor,
+                    canvas_pixel[2] * (1.0 - factor) + current_pixel[2] as f32 * factor,
+                    255.0,
                 ]);
-            } else if decay_amount > 0.0 {
+                if let Some(ages) = state.trail_ages.as_mut() {
+                    ages.stamp(index, timestamp_ms);
+                }
+            } else if let Some(ages) = state.trail_ages.as_mut()
+                && ages.is_expired(index, timestamp_ms, settings.max_trail_age_ms)
+            {
+                // Discard trails that outlived the maximum age
+                let current_pixel = *current_frame.get_pixel(x, y);
+                *canvas_pixel = image::Rgba(current_pixel.0.map(f32::from));
+                ages.clear(index);
+            } else if decay_pass.is_active() {
                 // Decay the canvas towards the current frame in static areas
                 let current_pixel = *current_frame.get_pixel(x, y);
+                let pixel_decay = decay_pass.for_pixel(state.trail_ages.as_ref(), index);
                 *canvas_pixel = image::Rgba([
-                    move_towards(canvas_pixel[0], current_pixel[0], decay_amount),
-                    move_towards(canvas_pixel[1], current_pixel[1], decay_amount),
-                    move_towards(canvas_pixel[2], current_pixel[2], decay_amount),
-                    255,
+                    pixel_decay.apply(canvas_pixel[0], current_pixel[0] as f32),
+                    pixel_decay.apply(canvas_pixel[1], current_pixel[1] as f32),
+                    pixel_decay.apply(canvas_pixel[2], current_pixel[2] as f32),
+                    255.0,
                 ]);
             }
         }
+        state.last_decay_ms = Some(timestamp_ms);
     }
 
-    apply_compositing_and_correction(
+    let mut output_frame = apply_compositing_and_correction(
         current_frame.clone(),
         canvas,
         current_frame,
-        &motion_mask,
+        motion_mask.as_slice(),
+        settings.stable.blend_mode,
         settings,
-    )
+    );
+
+    if settings.glow.enabled {
+        let trails = canvas_glow_layer(canvas, current_frame, state.trail_ages.as_ref(), motion_mask, motion_thresh);
+        apply_glow(&mut output_frame, &trails, &settings.glow);
+    }
+
+    output_frame
+}
+
+/// Extracts the burned-in trails of the canvas as a glow layer: pixels that differ from the live
+/// `current_frame` by more than `threshold`, and still have a trail if the ages are tracked. The
+/// moving object itself is left out, so only its trail glows.
+fn canvas_glow_layer(
+    canvas: &Rgba32FImage,
+    current_frame: &RgbaImage,
+    trail_ages: Option<&TrailAges>,
+    motion_mask: &MotionMask,
+    threshold: i16,
+) -> Rgba32FImage {
+    let (width, height) = canvas.dimensions();
+    Rgba32FImage::from_fn(width, height, |x, y| {
+        let index = (y * width + x) as usize;
+        let canvas_pixel = canvas.get_pixel(x, y);
+        let current_pixel = current_frame.get_pixel(x, y);
+        let has_trail = trail_ages.is_none_or(|ages| ages.has_trail(index));
+        let is_trail = (0..3).any(|c| (canvas_pixel[c] - current_pixel[c] as f32).abs() > threshold as f32);
+        if has_trail && is_trail && !motion_mask.is_moving(index) {
+            *canvas_pixel
+        } else {
+            Rgba([0.0, 0.0, 0.0, 0.0])
+        }
+    })
 }
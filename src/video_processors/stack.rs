@@ -0,0 +1,110 @@
+use image::RgbaImage;
+use serde::Deserialize;
+use std::error::Error;
+
+use crate::video_processors::{
+    EffectMode,
+    EffectSettings,
+    EffectState,
+    process_frame,
+    utils::{
+        MotionMask,
+        create_motion_mask,
+        to_byte_image,
+    },
+};
+
+/// One effect in a stack, fed with the output of the stage before it.
+#[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EffectStage {
+    pub mode: EffectMode,
+    /// Overrides the `motion_threshold_percent` of the effect settings for this stage.
+    pub motion_threshold_percent: Option<f32>,
+}
+
+/// Runs a list of effects one after another, each with its own canvas and decay state.
+pub struct EffectStack {
+    stages: Vec<EffectStage>,
+    states: Vec<Option<EffectState>>,
+}
+
+impl EffectStack {
+    /// Creates a stack with fresh state for every stage.
+    pub fn new(stages: Vec<EffectStage>) -> Self {
+        let states = stages.iter().map(|_| None).collect();
+        EffectStack { stages, states }
+    }
+
+    /// Creates a stack running only the effect selected by `mode`.
+    pub fn single(mode: EffectMode) -> Self {
+        EffectStack::new(vec![EffectStage { mode, motion_threshold_percent: None }])
+    }
+
+    /// Returns a copy of the state of every stage, none for stages that haven't run yet.
+    pub fn states(&self) -> Vec<Option<EffectState>> {
+        self.states.clone()
+    }
+
+    /// Replaces the state of every stage with `states`, as returned by `states`.
+    pub fn restore(&mut self, states: Vec<Option<EffectState>>) -> Result<(), Box<dyn Error>> {
+        if states.len() != self.stages.len() {
+            return Err("The checkpoint was saved with another effect stack".into());
+        }
+        self.states = states;
+        Ok(())
+    }
+
+    /// Returns the canvas of the last stage as an 8-bit image, none before it has run.
+    pub fn canvas(&self) -> Option<RgbaImage> {
+        let state = self.states.last()?.as_ref()?;
+        Some(to_byte_image(&state.canvas))
+    }
+
+    /// Processes a frame through every stage. Motion is always detected between the `source`
+    /// frame and the `previous_source` frame, and stages with the same detector settings share
+    /// one motion mask.
+    pub fn process(
+        &mut self,
+        source: &RgbaImage,
+        previous_source: &RgbaImage,
+        settings: &EffectSettings,
+        is_update_frame: bool,
+        timestamp_ms: f64,
+    ) -> RgbaImage {
+        let mut motion_masks: Vec<(i16, MotionMask)> = Vec::new();
+        let mut frame = source.clone();
+
+        for (stage, state) in self.stages.iter().zip(&mut self.states) {
+            let mut stage_settings = settings.clone();
+            stage_settings.mode = stage.mode;
+            if let Some(threshold) = stage.motion_threshold_percent {
+                stage_settings.motion_threshold_percent = threshold;
+            }
+
+            let motion_thresh = (255.0 * stage_settings.motion_threshold_percent) as i16;
+            let mask_index = match motion_masks.iter().position(|(thresh, _)| *thresh == motion_thresh) {
+                Some(index) => index,
+                None => {
+                    motion_masks.push((motion_thresh, create_motion_mask(source, previous_source, motion_thresh)));
+                    motion_masks.len() - 1
+                }
+            };
+
+            // Initialize the stage state on its first frame
+            let is_first_frame = state.is_none();
+            let state = state.get_or_insert_with(|| EffectState::new(&frame, &stage_settings, timestamp_ms));
+
+            frame = process_frame(
+                state,
+                &frame,
+                &motion_masks[mask_index].1,
+                &stage_settings,
+                is_update_frame && !is_first_frame,
+                timestamp_ms,
+            );
+        }
+
+        frame
+    }
+}
@@ -0,0 +1,58 @@
+/// Records, for every canvas pixel, the timestamp at which it last received a trail.
+/// Stored alongside the canvas so effects can compute the exact age of a trail instead of
+/// guessing it from how far its color has decayed.
+#[derive(Clone)]
+pub struct TrailAges {
+    /// The timestamps in milliseconds, NaN for pixels without a trail. Single precision keeps
+    /// the buffer at 4 bytes per pixel, and still resolves 4 ms after 10 hours.
+    last_stamp_ms: Vec<f32>,
+}
+
+impl TrailAges {
+    /// Creates an empty buffer for a canvas of the given size, with no trails recorded.
+    pub fn new(width: u32, height: u32) -> Self {
+        TrailAges {
+            last_stamp_ms: vec![f32::NAN; (width * height) as usize],
+        }
+    }
+
+    /// Creates a buffer holding `stamps`, as returned by `stamps`.
+    pub fn from_stamps(stamps: Vec<f32>) -> Self {
+        TrailAges { last_stamp_ms: stamps }
+    }
+
+    /// Returns the timestamp of the last trail of every pixel in milliseconds, NaN for pixels
+    /// without a trail.
+    pub fn stamps(&self) -> &[f32] {
+        &self.last_stamp_ms
+    }
+
+    /// Records that the pixel at `index` received a trail at `timestamp_ms`.
+    pub fn stamp(&mut self, index: usize, timestamp_ms: f64) {
+        self.last_stamp_ms[index] = timestamp_ms as f32;
+    }
+
+    /// Forgets the trail at `index`.
+    pub fn clear(&mut self, index: usize) {
+        self.last_stamp_ms[index] = f32::NAN;
+    }
+
+    /// Returns `true` if the pixel at `index` has a trail.
+    pub fn has_trail(&self, index: usize) -> bool {
+        !self.last_stamp_ms[index].is_nan()
+    }
+
+    /// Returns the age of the trail at `index` in milliseconds, or `None` if it has no trail.
+    pub fn age_ms(&self, index: usize, timestamp_ms: f64) -> Option<f64> {
+        let stamped = self.last_stamp_ms[index];
+        (!stamped.is_nan()).then(|| (timestamp_ms - stamped as f64).max(0.0))
+    }
+
+    /// Returns `true` if the trail at `index` is older than `max_age_ms`.
+    pub fn is_expired(&self, index: usize, timestamp_ms: f64, max_age_ms: Option<u32>) -> bool {
+        match (max_age_ms, self.age_ms(index, timestamp_ms)) {
+            (Some(max_age), Some(age)) => age > max_age as f64,
+            _ => false,
+        }
+    }
+}
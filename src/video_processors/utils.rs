@@ -1,51 +1,169 @@
-use image::RgbaImage;
+use image::{
+    Rgba,
+    Rgba32FImage,
+    RgbaImage,
+};
+
+use serde::Deserialize;
 
 use crate::video_processors::EffectSettings;
 
-/// Linearly interpolates a `current` u8 value towards a `target` u8 value by a fixed `amount`.
-pub fn move_towards(current: u8, target: u8, amount: f32) -> u8 {
+/// Linearly interpolates a `current` value towards a `target` value by a fixed `amount`.
+pub fn move_towards(current: f32, target: f32, amount: f32) -> f32 {
     if amount <= 0.0 {
         return current;
     }
-    let current_f = current as f32;
-    let target_f = target as f32;
-    if current_f < target_f {
-        (current_f + amount).min(target_f) as u8
-    } else if current_f > target_f {
-        (current_f - amount).max(target_f) as u8
+    if current < target {
+        (current + amount).min(target)
+    } else if current > target {
+        (current - amount).max(target)
     } else {
         current
     }
 }
 
+/// Converts an 8-bit frame into a floating-point canvas. Canvas values keep the 0.0 to 255.0 scale
+/// of the frame, so small per-frame changes are not lost to rounding.
+pub fn to_float_canvas(frame: &RgbaImage) -> Rgba32FImage {
+    let (width, height) = frame.dimensions();
+    Rgba32FImage::from_fn(width, height, |x, y| {
+        let pixel = frame.get_pixel(x, y);
+        Rgba([pixel[0] as f32, pixel[1] as f32, pixel[2] as f32, pixel[3] as f32])
+    })
+}
+
+/// Converts a floating-point canvas back into an 8-bit image, rounding and clamping each channel.
+pub fn to_byte_image(canvas: &Rgba32FImage) -> RgbaImage {
+    let (width, height) = canvas.dimensions();
+    RgbaImage::from_fn(width, height, |x, y| {
+        Rgba(canvas.get_pixel(x, y).0.map(|channel| channel.round().clamp(0.0, 255.0) as u8))
+    })
+}
+
+/// The motion detected between two frames.
+pub struct MotionMask {
+    /// Whether each pixel changed by more than the threshold.
+    moving: Vec<bool>,
+    /// The largest channel difference of each pixel (0 to 255).
+    difference: Vec<u8>,
+}
+
+impl MotionMask {
+    /// Returns `true` if the pixel at `index` is in motion.
+    pub fn is_moving(&self, index: usize) -> bool {
+        self.moving[index]
+    }
+
+    /// Returns the strength of the change at the pixel at `index` (0.0 to 1.0).
+    pub fn intensity(&self, index: usize) -> f32 {
+        self.difference[index] as f32 / 255.0
+    }
+
+    /// Returns the mask as one flag per pixel.
+    pub fn as_slice(&self) -> &[bool] {
+        &self.moving
+    }
+}
+
 /// Creates a motion mask by comparing the `current` and `prev` frames.
-pub fn create_motion_mask(current: &RgbaImage, prev: &RgbaImage, threshold: i16) -> Vec<bool> {
+pub fn create_motion_mask(current: &RgbaImage, prev: &RgbaImage, threshold: i16) -> MotionMask {
     let (width, height) = current.dimensions();
-    let mut mask = vec![false; (width * height) as usize];
+    let mut moving = vec![false; (width * height) as usize];
+    let mut difference = vec![0; (width * height) as usize];
     for (x, y, current_pixel) in current.enumerate_pixels() {
         let prev_pixel = prev.get_pixel(x, y);
         let diff_r = (current_pixel[0] as i16 - prev_pixel[0] as i16).abs();
         let diff_g = (current_pixel[1] as i16 - prev_pixel[1] as i16).abs();
         let diff_b = (current_pixel[2] as i16 - prev_pixel[2] as i16).abs();
+        let index = (y * width + x) as usize;
         if diff_r > threshold || diff_g > threshold || diff_b > threshold {
-            mask[(y * width + x) as usize] = true;
+            moving[index] = true;
         }
+        difference[index] = diff_r.max(diff_g).max(diff_b) as u8;
     }
-    mask
+    MotionMask { moving, difference }
+}
+
+/// Defines how a trail layer is composited onto the frame below it.
+#[allow(dead_code)]
+#[derive(Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BlendMode {
+    /// The trail replaces the frame.
+    Normal,
+    /// The trail is added onto the frame, saturating at white.
+    Add,
+    /// Brightens the frame, like projecting the trail onto it.
+    Screen,
+    /// Darkens the frame by multiplying it with the trail.
+    Multiply,
+    /// Multiplies dark areas and screens bright areas of the frame, boosting contrast.
+    Overlay,
+    /// Keeps the lighter of the trail and the frame.
+    Lighten,
+    /// Keeps the darker of the trail and the frame.
+    Darken,
+    /// The absolute difference between the trail and the frame.
+    Difference,
+    /// A gentler overlay that lightens or darkens the frame depending on the trail.
+    SoftLight,
+}
+
+/// Blends a `top` channel value onto a `base` channel value, both on the 0.0 to 255.0 scale.
+pub fn blend_channel(base: f32, top: f32, mode: BlendMode) -> f32 {
+    let a = (base / 255.0).clamp(0.0, 1.0);
+    let b = (top / 255.0).clamp(0.0, 1.0);
+    let blended = match mode {
+        BlendMode::Normal => b,
+        BlendMode::Add => (a + b).min(1.0),
+        BlendMode::Screen => 1.0 - (1.0 - a) * (1.0 - b),
+        BlendMode::Multiply => a * b,
+        BlendMode::Overlay => {
+            if a < 0.5 {
+                2.0 * a * b
+            } else {
+                1.0 - 2.0 * (1.0 - a) * (1.0 - b)
+            }
+        }
+        BlendMode::Lighten => a.max(b),
+        BlendMode::Darken => a.min(b),
+        BlendMode::Difference => (a - b).abs(),
+        BlendMode::SoftLight => {
+            if b <= 0.5 {
+                a - (1.0 - 2.0 * b) * a * (1.0 - a)
+            } else {
+                let d = if a <= 0.25 { ((16.0 * a - 12.0) * a + 4.0) * a } else { a.sqrt() };
+                a + (2.0 * b - 1.0) * (d - a)
+            }
+        }
+    };
+    blended * 255.0
+}
+
+/// Blends a `top` pixel onto a `base` pixel with the given `mode`, then mixes the result with the
+/// base by `opacity` (0.0 to 1.0).
+pub fn blend_pixel(base: &Rgba<u8>, top: &Rgba<f32>, mode: BlendMode, opacity: f32) -> Rgba<u8> {
+    let mix = |c: usize| {
+        let base_c = base[c] as f32;
+        let blended = blend_channel(base_c, top[c], mode);
+        (base_c + (blended - base_c) * opacity).round().clamp(0.0, 255.0) as u8
+    };
+    Rgba([mix(0), mix(1), mix(2), 255])
 }
 
 /// Applies compositing and edge correction to the output frame.
 pub fn apply_compositing_and_correction(
     mut output_frame: RgbaImage,
-    canvas: &RgbaImage,
+    canvas: &Rgba32FImage,
     current_frame: &RgbaImage,
     motion_mask: &[bool],
+    blend_mode: BlendMode,
     settings: &EffectSettings,
 ) -> RgbaImage {
     let (width, height) = output_frame.dimensions();
     for (x, y, pixel) in output_frame.enumerate_pixels_mut() {
         if !motion_mask[(y * width + x) as usize] {
-            *pixel = *canvas.get_pixel(x, y);
+            *pixel = blend_pixel(pixel, canvas.get_pixel(x, y), blend_mode, 1.0);
         }
     }
 
@@ -72,6 +190,45 @@ pub fn apply_compositing_and_correction(
     output_frame
 }
 
+/// Separates the trails from a processed frame. Returns the layer that gives `output_frame` when
+/// composited over `source_frame` with normal blending, using the lowest alpha that does, so
+/// pixels the effect left unchanged are fully transparent.
+pub fn extract_trail_layer(source_frame: &RgbaImage, output_frame: &RgbaImage) -> RgbaImage {
+    let (width, height) = output_frame.dimensions();
+    RgbaImage::from_fn(width, height, |x, y| {
+        let source_pixel = source_frame.get_pixel(x, y);
+        let output_pixel = output_frame.get_pixel(x, y);
+
+        // The alpha each channel needs to move from the source value to the output value
+        let alpha = (0..3)
+            .map(|c| {
+                let (source, output) = (source_pixel[c] as f32, output_pixel[c] as f32);
+                if output > source {
+                    (output - source) / (255.0 - source)
+                } else if output < source {
+                    (source - output) / source
+                } else {
+                    0.0
+                }
+            })
+            .fold(0.0, f32::max);
+        if alpha <= 0.0 {
+            return Rgba([0, 0, 0, 0]);
+        }
+
+        let color = |c: usize| {
+            let source = source_pixel[c] as f32;
+            (source + (output_pixel[c] as f32 - source) / alpha).round().clamp(0.0, 255.0) as u8
+        };
+        Rgba([color(0), color(1), color(2), (alpha * 255.0).round() as u8])
+    })
+}
+
+/// Computes the perceived luminance of a pixel (0.0 to 255.0), using Rec. 709 weights.
+pub fn luminance(pixel: &image::Rgba<u8>) -> f32 {
+    0.2126 * pixel[0] as f32 + 0.7152 * pixel[1] as f32 + 0.0722 * pixel[2] as f32
+}
+
 /// Converts a color from HSV to RGB.
 ///
 /// # Arguments
@@ -0,0 +1,253 @@
+use log::{
+    error,
+    info,
+    warn,
+};
+use serde::Deserialize;
+use std::{
+    collections::{
+        HashMap,
+        HashSet,
+    },
+    error::Error,
+    fs::{
+        self,
+        OpenOptions,
+    },
+    io::Write,
+    path::{
+        Path,
+        PathBuf,
+    },
+    thread,
+    time::{
+        Duration,
+        Instant,
+        SystemTime,
+        UNIX_EPOCH,
+    },
+};
+
+use crate::{
+    batch::{
+        clip_config,
+        find_videos,
+        output_path,
+    },
+    checkpoint::Checkpointer,
+    config::Config,
+    interrupt,
+};
+
+/// The directory inside the watched directory that holds the file being processed. A file left in
+/// it was interrupted and is processed again on the next start, from its checkpoint if it has one.
+const PROCESSING_DIR: &str = ".processing";
+
+/// Configuration for the watch mode, which runs until stopped and processes every video that
+/// appears in a directory.
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WatchSettings {
+    /// The directory to watch, none to process `input.path` only.
+    pub input: Option<PathBuf>,
+    /// The directory the outputs are written to, created if it doesn't exist.
+    pub output_dir: PathBuf,
+    /// The file name of each output, as in the batch mode.
+    pub name_template: String,
+    /// The directory sources are moved to once they are processed.
+    pub done_dir: PathBuf,
+    /// The directory sources are moved to if processing them failed.
+    pub failed_dir: PathBuf,
+    /// The preset the videos are processed with, the rest of this config if none.
+    pub preset: Option<String>,
+    /// The directory holding the presets, each a config file named after it.
+    pub presets_dir: PathBuf,
+    /// The time in seconds a file's size must stay unchanged before it is considered completely
+    /// written.
+    pub stable_secs: f64,
+    /// The time in seconds between two scans of the directory.
+    pub poll_interval_secs: f64,
+    /// The file a line is appended to for every job.
+    pub log_path: PathBuf,
+}
+
+impl Default for WatchSettings {
+    fn default() -> Self {
+        WatchSettings {
+            input: None,
+            output_dir: PathBuf::from("output"),
+            name_template: String::from("{stem}_sticky.mp4"),
+            done_dir: PathBuf::from("done"),
+            failed_dir: PathBuf::from("failed"),
+            preset: None,
+            presets_dir: PathBuf::from("presets"),
+            stable_secs: 5.0,
+            poll_interval_secs: 1.0,
+            log_path: PathBuf::from("jobs.log"),
+        }
+    }
+}
+
+impl WatchSettings {
+    /// Returns `true` if a directory to watch is set.
+    pub fn is_enabled(&self) -> bool {
+        self.input.is_some()
+    }
+
+    /// Loads the config the videos are processed with, read again for every job so that changes
+    /// to the preset apply without a restart.
+    fn job_config(&self, config: &Config) -> Result<Config, Box<dyn Error>> {
+        let Some(preset) = &self.preset else {
+            return Ok(config.clone());
+        };
+        let path = self.presets_dir.join(format!("{}.toml", preset));
+        if !path.exists() {
+            return Err(format!("The preset '{}' doesn't exist, expected {}", preset, path.display()).into());
+        }
+        let preset_config = Config::load(&path)?;
+        if preset_config.preview.enabled
+            || preset_config.live.enabled
+            || preset_config.batch.is_enabled()
+            || preset_config.watch.is_enabled()
+        {
+            return Err(format!("The preset '{}' can't enable the preview, live, batch or watch mode", preset).into());
+        }
+        Ok(preset_config)
+    }
+}
+
+/// Watches `config.watch.input` until the process is stopped, processing every video with
+/// `render` once it is completely written. `render` is told whether to resume from a checkpoint.
+pub fn run(config: &Config, render: impl Fn(&Config, bool) -> Result<(), Box<dyn Error>>) -> Result<(), Box<dyn Error>> {
+    let watch = &config.watch;
+    let input_dir = watch.input.as_deref().ok_or("watch: no input is set")?;
+    let processing_dir = input_dir.join(PROCESSING_DIR);
+    for dir in [&processing_dir, &watch.output_dir, &watch.done_dir, &watch.failed_dir] {
+        fs::create_dir_all(dir)?;
+    }
+    // Check the preset before waiting for the first video
+    watch.job_config(config)?;
+
+    // Put interrupted files back, to be processed again
+    let mut interrupted = HashSet::new();
+    for path in find_videos(&processing_dir)? {
+        warn!("{} was interrupted, it will be processed again", path.display());
+        let input_path = input_dir.join(path.file_name().ok_or("Invalid file name")?);
+        fs::rename(&path, &input_path)?;
+        interrupted.insert(input_path);
+    }
+
+    info!("Watching {} for new videos", input_dir.display());
+    let poll_interval = Duration::from_secs_f64(watch.poll_interval_secs);
+    let stable_duration = Duration::from_secs_f64(watch.stable_secs);
+    // The size and modification time of every file seen, and since when they are unchanged
+    let mut seen: HashMap<PathBuf, (u64, Option<SystemTime>, Instant)> = HashMap::new();
+    loop {
+        interrupt::check()?;
+        let videos = find_videos(input_dir)?;
+        seen.retain(|path, _| videos.contains(path));
+
+        for path in videos {
+            let Ok(metadata) = fs::metadata(&path) else {
+                continue;
+            };
+            let state = (metadata.len(), metadata.modified().ok());
+            let since = match seen.get(&path) {
+                Some(&(len, modified, since)) if (len, modified) == state => since,
+                _ => {
+                    seen.insert(path.clone(), (state.0, state.1, Instant::now()));
+                    continue;
+                }
+            };
+            if since.elapsed() < stable_duration {
+                continue;
+            }
+
+            seen.remove(&path);
+            let was_interrupted = interrupted.remove(&path);
+            if let Err(e) = process(config, &path, &processing_dir, was_interrupted, &render) {
+                interrupt::check()?;
+                error!("Failed to process {}: {}", path.display(), e);
+            }
+        }
+
+        thread::sleep(poll_interval);
+    }
+}
+
+/// Processes the completely written video at `path`, then moves it to the done or failed
+/// directory and logs the job. An interrupted job is resumed from its checkpoint if it has one.
+fn process(
+    config: &Config,
+    path: &Path,
+    processing_dir: &Path,
+    was_interrupted: bool,
+    render: &impl Fn(&Config, bool) -> Result<(), Box<dyn Error>>,
+) -> Result<(), Box<dyn Error>> {
+    let watch = &config.watch;
+    let file_name = path.file_name().ok_or("Invalid file name")?;
+    let start = Instant::now();
+    info!("Processing {}", path.display());
+
+    // The source is moved out of the watched directory while it is processed, which marks it as
+    // in progress if the process stops
+    let processing_path = processing_dir.join(file_name);
+    fs::rename(path, &processing_path)?;
+    let output = output_path(&watch.output_dir, &watch.name_template, path);
+    let resume = was_interrupted && Checkpointer::exists(&output);
+    let result = watch.job_config(config).and_then(|job_config| {
+        render(&clip_config(&job_config, &processing_path, &output), resume)
+    });
+    // A job stopped by Ctrl-C stays in progress, to be processed again on the next start
+    if interrupt::is_interrupted() {
+        return interrupt::check();
+    }
+
+    let target_dir = if result.is_ok() { &watch.done_dir } else { &watch.failed_dir };
+    let target_path = free_path(&target_dir.join(file_name));
+    fs::rename(&processing_path, &target_path)?;
+
+    let status = match &result {
+        Ok(()) => {
+            info!("Done with {}, moved to {}", path.display(), target_path.display());
+            String::from("done")
+        }
+        Err(e) => {
+            error!("Failed to process {}, moved to {}: {}", path.display(), target_path.display(), e);
+            format!("failed: {}", e)
+        }
+    };
+    log_job(&watch.log_path, path, &output, start.elapsed(), &status)
+}
+
+/// Returns `path`, or if it is taken, the path with the lowest number appended to its stem that
+/// isn't.
+fn free_path(path: &Path) -> PathBuf {
+    let stem = path.file_stem().map(|stem| stem.to_string_lossy()).unwrap_or_default();
+    let extension = path.extension().map(|extension| format!(".{}", extension.to_string_lossy())).unwrap_or_default();
+    let mut candidate = path.to_path_buf();
+    for number in 1.. {
+        if !candidate.exists() {
+            break;
+        }
+        candidate = path.with_file_name(format!("{}_{}{}", stem, number, extension));
+    }
+    candidate
+}
+
+/// Appends a tab-separated line to the job log: the Unix time the job ended, the input, the
+/// output, the duration in seconds and the status.
+fn log_job(log_path: &Path, input: &Path, output: &Path, duration: Duration, status: &str) -> Result<(), Box<dyn Error>> {
+    let time = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let mut log = OpenOptions::new().create(true).append(true).open(log_path)?;
+    writeln!(
+        log,
+        "{}\t{}\t{}\t{:.1}\t{}",
+        time,
+        input.display(),
+        output.display(),
+        duration.as_secs_f64(),
+        status.replace(['\t', '\n'], " ")
+    )?;
+    Ok(())
+}